@@ -1 +1 @@
-curl -w @curl-format.txt -o /dev/null -s https://teams.european-aerostudent-games.com/view/teams/7968ea5ba89b72d24110ceece60da9c8
+curl -w @curl-format.txt -o /dev/null -s https://teams.european-aerostudent-games.com/view/e/games/teams
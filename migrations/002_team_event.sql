@@ -0,0 +1,16 @@
+-- Up
+-- Teams registered before multi-event support all belong to the same Attendize event
+SET @existing_teams_event_id = 2;
+
+ALTER TABLE teams ADD COLUMN event_id INT(10) UNSIGNED NULL AFTER id;
+UPDATE teams SET event_id = @existing_teams_event_id;
+ALTER TABLE teams
+    MODIFY event_id INT(10) UNSIGNED NOT NULL,
+    ADD CONSTRAINT fk_teams_event FOREIGN KEY (event_id) REFERENCES events(id),
+    ADD INDEX idx_teams_event_sport (event_id, sport);
+
+-- Down
+ALTER TABLE teams
+    DROP FOREIGN KEY fk_teams_event,
+    DROP INDEX idx_teams_event_sport,
+    DROP COLUMN event_id;
@@ -0,0 +1,41 @@
+-- Up
+-- Lists attendees registered in several teams of the same sport, they are
+-- removed below from all of these teams but the one they joined first
+SELECT tm.attendee_id, a.first_name, a.last_name, t.event_id, t.sport,
+    GROUP_CONCAT(CONCAT(t.name, ' (', t.uuid, ')') SEPARATOR ', ') teams
+FROM team_members tm
+JOIN teams t ON tm.team_id = t.id
+JOIN attendees a ON tm.attendee_id = a.id
+GROUP BY tm.attendee_id, a.first_name, a.last_name, t.event_id, t.sport
+HAVING COUNT(*) > 1;
+
+-- The sport of a member is copied from its team, the foreign key keeps both in sync
+ALTER TABLE teams ADD UNIQUE KEY uq_teams_event_sport (id, event_id, sport);
+
+ALTER TABLE team_members
+    ADD COLUMN event_id INT(10) UNSIGNED NULL AFTER team_id,
+    ADD COLUMN sport VARCHAR(32) NULL AFTER event_id;
+UPDATE team_members tm JOIN teams t ON tm.team_id = t.id
+SET tm.event_id = t.event_id, tm.sport = t.sport;
+
+DELETE tm FROM team_members tm
+JOIN team_members earlier ON earlier.attendee_id = tm.attendee_id
+    AND earlier.event_id = tm.event_id
+    AND earlier.sport = tm.sport
+    AND earlier.id < tm.id;
+
+-- One team per sport per attendee
+ALTER TABLE team_members
+    MODIFY event_id INT(10) UNSIGNED NOT NULL,
+    MODIFY sport VARCHAR(32) NOT NULL,
+    ADD CONSTRAINT fk_team_members_team_sport FOREIGN KEY (team_id, event_id, sport)
+        REFERENCES teams(id, event_id, sport) ON DELETE CASCADE ON UPDATE CASCADE,
+    ADD UNIQUE KEY uq_team_members_sport (attendee_id, event_id, sport);
+
+-- Down
+ALTER TABLE team_members
+    DROP FOREIGN KEY fk_team_members_team_sport,
+    DROP INDEX uq_team_members_sport,
+    DROP COLUMN sport,
+    DROP COLUMN event_id;
+ALTER TABLE teams DROP INDEX uq_teams_event_sport;
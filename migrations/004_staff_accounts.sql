@@ -0,0 +1,22 @@
+-- Up
+CREATE TABLE staff_accounts(
+    id INT(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+    username VARCHAR(64) NOT NULL,
+    -- Argon2 PHC string, see `pegalpha hash-password`
+    password_hash VARCHAR(256) NOT NULL,
+    role ENUM('sport_admin', 'checkin_agent', 'delegation_leader', 'read_only') NOT NULL,
+    -- NULL gives access to every event
+    event_id INT(10) UNSIGNED NULL,
+    -- School of a delegation leader
+    school_id INT(10) UNSIGNED NULL,
+    -- Revoked accounts are kept, their sessions stop working immediately
+    disabled_at DATETIME NULL,
+    created_at DATETIME NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    UNIQUE KEY uq_staff_accounts_username (username),
+    FOREIGN KEY (event_id) REFERENCES events(id),
+    FOREIGN KEY (school_id) REFERENCES question_options(id)
+) ENGINE=INNODB;
+
+-- Down
+DROP TABLE staff_accounts;
@@ -0,0 +1,18 @@
+-- Up
+CREATE TABLE audit_log(
+    id INT(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+    event_id INT(10) UNSIGNED NOT NULL,
+    team_id INT(10) UNSIGNED NULL,
+    -- Who made the change, example : 'staff:jdoe' or 'captain:1234'
+    actor VARCHAR(128) NOT NULL,
+    action VARCHAR(64) NOT NULL,
+    details TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    INDEX idx_audit_log_event (event_id, created_at),
+    FOREIGN KEY (event_id) REFERENCES events(id),
+    FOREIGN KEY (team_id) REFERENCES teams(id) ON DELETE SET NULL
+) ENGINE=INNODB;
+
+-- Down
+DROP TABLE audit_log;
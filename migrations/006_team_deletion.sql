@@ -0,0 +1,21 @@
+-- Up
+-- Deleted teams are kept for auditing and can be restored by the sport team
+ALTER TABLE teams
+    ADD COLUMN deleted_at DATETIME NULL,
+    ADD COLUMN deleted_by VARCHAR(128) NULL;
+
+-- Members of deleted teams, moved out of team_members so that they can join another team
+CREATE TABLE deleted_team_members(
+    id INT(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+    team_id INT(10) UNSIGNED NOT NULL,
+    attendee_id INT(10) UNSIGNED NOT NULL,
+    PRIMARY KEY(id),
+    FOREIGN KEY (team_id) REFERENCES teams(id) ON DELETE CASCADE,
+    FOREIGN KEY (attendee_id) REFERENCES attendees(id)
+) ENGINE=INNODB;
+
+-- Down
+DROP TABLE deleted_team_members;
+ALTER TABLE teams
+    DROP COLUMN deleted_by,
+    DROP COLUMN deleted_at;
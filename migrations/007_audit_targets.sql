@@ -0,0 +1,25 @@
+-- Up
+ALTER TABLE audit_log
+    ADD COLUMN attendee_id INT(10) UNSIGNED NULL AFTER team_id,
+    ADD COLUMN before_value TEXT NULL AFTER details,
+    ADD COLUMN after_value TEXT NULL AFTER before_value,
+    ADD INDEX idx_audit_log_team (team_id, created_at),
+    ADD INDEX idx_audit_log_attendee (attendee_id, created_at),
+    ADD CONSTRAINT fk_audit_log_attendee FOREIGN KEY (attendee_id) REFERENCES attendees(id) ON DELETE SET NULL;
+
+-- The log is append-only, rows can neither be changed nor removed by the application
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'audit_log is append-only';
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'audit_log is append-only';
+
+-- Down
+DROP TRIGGER audit_log_no_delete;
+DROP TRIGGER audit_log_no_update;
+ALTER TABLE audit_log
+    DROP FOREIGN KEY fk_audit_log_attendee,
+    DROP INDEX idx_audit_log_attendee,
+    DROP INDEX idx_audit_log_team,
+    DROP COLUMN after_value,
+    DROP COLUMN before_value,
+    DROP COLUMN attendee_id;
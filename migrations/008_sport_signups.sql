@@ -0,0 +1,26 @@
+-- Up
+-- Athletes who claimed a spot in a sport with a capacity
+CREATE TABLE sport_signups(
+    id INT(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+    event_id INT(10) UNSIGNED NOT NULL,
+    sport VARCHAR(32) NOT NULL,
+    attendee_id INT(10) UNSIGNED NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT NOW(),
+    PRIMARY KEY(id),
+    UNIQUE KEY uq_sport_signups (event_id, sport, attendee_id),
+    FOREIGN KEY (event_id) REFERENCES events(id),
+    FOREIGN KEY (attendee_id) REFERENCES attendees(id)
+) ENGINE=INNODB;
+
+-- One row per sport, locked while a spot is claimed so that claims of the
+-- same sport are counted one after the other
+CREATE TABLE sport_signup_locks(
+    event_id INT(10) UNSIGNED NOT NULL,
+    sport VARCHAR(32) NOT NULL,
+    PRIMARY KEY(event_id, sport),
+    FOREIGN KEY (event_id) REFERENCES events(id)
+) ENGINE=INNODB;
+
+-- Down
+DROP TABLE sport_signup_locks;
+DROP TABLE sport_signups;
@@ -0,0 +1,12 @@
+-- Up
+-- Athletes who sign up to a full sport wait in the order of their id
+ALTER TABLE sport_signups
+    ADD COLUMN status ENUM('confirmed', 'waiting') NOT NULL DEFAULT 'confirmed' AFTER attendee_id,
+    ADD COLUMN promoted_at DATETIME NULL AFTER created_at,
+    ADD INDEX idx_sport_signups_queue (event_id, sport, status, id);
+
+-- Down
+ALTER TABLE sport_signups
+    DROP INDEX idx_sport_signups_queue,
+    DROP COLUMN promoted_at,
+    DROP COLUMN status;
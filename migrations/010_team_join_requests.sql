@@ -0,0 +1,26 @@
+-- Up
+-- Code that teammates type or open as a link to ask to join a team
+ALTER TABLE teams
+    ADD COLUMN join_code CHAR(8) NULL AFTER uuid,
+    ADD UNIQUE KEY uq_teams_join_code (join_code);
+UPDATE teams SET join_code = UPPER(LEFT(MD5(CONCAT(uuid, RAND())), 8)) WHERE join_code IS NULL;
+
+-- Requests to join a team, accepted or declined by its captain
+CREATE TABLE team_join_requests(
+    id INT(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+    team_id INT(10) UNSIGNED NOT NULL,
+    attendee_id INT(10) UNSIGNED NOT NULL,
+    status ENUM('pending', 'accepted', 'declined') NOT NULL DEFAULT 'pending',
+    created_at DATETIME NOT NULL DEFAULT NOW(),
+    decided_at DATETIME NULL,
+    PRIMARY KEY(id),
+    UNIQUE KEY uq_team_join_requests (team_id, attendee_id),
+    FOREIGN KEY (team_id) REFERENCES teams(id) ON DELETE CASCADE,
+    FOREIGN KEY (attendee_id) REFERENCES attendees(id)
+) ENGINE=INNODB;
+
+-- Down
+DROP TABLE team_join_requests;
+ALTER TABLE teams
+    DROP KEY uq_teams_join_code,
+    DROP COLUMN join_code;
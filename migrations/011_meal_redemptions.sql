@@ -0,0 +1,32 @@
+-- Up
+-- Food tickets left to each attendee, filled from the entitlements of their
+-- ticket the first time a food stand scans them
+CREATE TABLE meal_balances(
+    event_id INT(10) UNSIGNED NOT NULL,
+    attendee_id INT(10) UNSIGNED NOT NULL,
+    breakfasts TINYINT UNSIGNED NOT NULL,
+    meals TINYINT UNSIGNED NOT NULL,
+    dinners TINYINT UNSIGNED NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT NOW(),
+    PRIMARY KEY(event_id, attendee_id),
+    FOREIGN KEY (event_id) REFERENCES events(id),
+    FOREIGN KEY (attendee_id) REFERENCES attendees(id)
+) ENGINE=INNODB;
+
+-- One row per meal served, an attendee is served at most once per service
+CREATE TABLE meal_redemptions(
+    id INT(10) UNSIGNED NOT NULL AUTO_INCREMENT,
+    event_id INT(10) UNSIGNED NOT NULL,
+    service VARCHAR(64) NOT NULL,
+    kind ENUM('breakfast', 'meal', 'dinner') NOT NULL,
+    attendee_id INT(10) UNSIGNED NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT NOW(),
+    PRIMARY KEY(id),
+    UNIQUE KEY uq_meal_redemptions (event_id, service, attendee_id),
+    FOREIGN KEY (event_id) REFERENCES events(id),
+    FOREIGN KEY (attendee_id) REFERENCES attendees(id)
+) ENGINE=INNODB;
+
+-- Down
+DROP TABLE meal_redemptions;
+DROP TABLE meal_balances;
@@ -0,0 +1,15 @@
+-- Up
+-- Cross Country registrations made by the former shotgun, which answered
+-- question 8 of event 2 in Attendize, become confirmed sign-ups in the order
+-- of their answer
+INSERT IGNORE INTO sport_signups(event_id, sport, attendee_id, status)
+SELECT a.event_id, 'Cross Country', qa.attendee_id, 'confirmed'
+FROM question_answers qa
+JOIN attendees a ON qa.attendee_id = a.id
+WHERE a.event_id = 2 AND qa.question_id = 8 AND qa.answer_text = 'Cross Country'
+ORDER BY qa.id;
+
+-- Down
+DELETE s FROM sport_signups s
+JOIN question_answers qa ON qa.attendee_id = s.attendee_id AND qa.question_id = 8 AND qa.answer_text = 'Cross Country'
+WHERE s.event_id = 2 AND s.sport = 'Cross Country';
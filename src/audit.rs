@@ -0,0 +1,280 @@
+//! Append-only trace of every change made to teams and check-ins
+
+use std::fmt;
+
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::Serialize;
+
+use rocket_db_pools::sqlx;
+use rocket_db_pools::sqlx::Row;
+use rocket_db_pools::sqlx::mysql::MySqlConnection;
+use rocket_db_pools::sqlx::types::chrono::NaiveDateTime;
+
+use crate::config::EventConfig;
+
+/**
+ * Header in which the scan app sends the id of the device it runs on
+ */
+pub const DEVICE_HEADER: &str = "X-Check-In-Device";
+
+/**
+ * Who made a change, as written in the audit_log table
+ */
+#[derive(Clone, Debug)]
+pub enum Actor {
+    /**
+     * Attendee id of a captain
+     */
+    Captain(u32),
+    /**
+     * Username of a staff account
+     */
+    Staff(String),
+    /**
+     * Username of a delegation leader, written apart from sport admins
+     * as they are only allowed the teams of their school
+     */
+    DelegationLeader(String),
+    /**
+     * Attendee reference typed in a public page (compose form, sign-up page),
+     * the person using it did not log in
+     */
+    Attendee(String),
+    /**
+     * Staff account scanning tickets, with the device of the scan app when it sent one
+     */
+    CheckIn { username: String, device: Option<String> },
+    /**
+     * Change made by the application itself, such as a promotion from a waiting list
+     */
+    System
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Actor::Captain(attendee_id) => write!(f, "captain:{attendee_id}"),
+            Actor::Staff(username) => write!(f, "staff:{username}"),
+            Actor::DelegationLeader(username) => write!(f, "delegation:{username}"),
+            Actor::Attendee(reference) => write!(f, "attendee:{reference}"),
+            Actor::CheckIn { username, device: Some(device) } => write!(f, "checkin:{username}@{device}"),
+            Actor::CheckIn { username, device: None } => write!(f, "checkin:{username}"),
+            Actor::System => write!(f, "system")
+        }
+    }
+}
+
+/**
+ * What a change touched, with the values before and after it when there are some
+ */
+#[derive(Default)]
+pub struct Change {
+    pub team_id: Option<u32>,
+    pub attendee_id: Option<u32>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub details: String
+}
+
+/**
+ * Records a change, to be called in the transaction of the change itself
+ * so that a change is never stored without its trace
+ */
+pub async fn record(db: &mut MySqlConnection, event: &EventConfig, actor: &Actor, action: &str, change: Change) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO audit_log(event_id, team_id, attendee_id, actor, action, details, before_value, after_value)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(event.event_id)
+    .bind(change.team_id)
+    .bind(change.attendee_id)
+    .bind(actor.to_string())
+    .bind(action)
+    .bind(change.details)
+    .bind(change.before)
+    .bind(change.after)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/**
+ * Id of the scan app device, sent in the X-Check-In-Device header
+ */
+pub struct Device(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Device {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        // The id is chosen by the device, keep it short enough for the actor column
+        let device = request.headers().get_one(DEVICE_HEADER)
+            .map(|d| d.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-').take(64).collect::<String>())
+            .filter(|d| !d.is_empty());
+        Outcome::Success(Device(device))
+    }
+}
+
+/**
+ * Filters of the audit log page, every filter is optional
+ */
+#[derive(Default)]
+pub struct AuditFilter {
+    /**
+     * Team uuid
+     */
+    pub team: Option<String>,
+    /**
+     * Attendee reference, example : Cy4st8O-2
+     */
+    pub attendee: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>
+}
+
+/**
+ * Filters as sent by the form of the audit log page, from and to come from
+ * datetime-local inputs, example : 2023-05-12T14:30
+ */
+#[derive(Serialize, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct AuditQuery {
+    pub team: Option<String>,
+    pub attendee: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuditQuery {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let field = |name: &str| request.query_value::<String>(name).and_then(|v| v.ok());
+        Outcome::Success(AuditQuery {
+            team: field("team"),
+            attendee: field("attendee"),
+            from: field("from"),
+            to: field("to")
+        })
+    }
+}
+
+impl AuditQuery {
+    /**
+     * Empty fields and invalid dates are ignored
+     */
+    pub fn filter(&self) -> AuditFilter {
+        let non_empty = |v: &Option<String>| v.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+        let parse_time = |v: &Option<String>| non_empty(v)
+            .and_then(|t| NaiveDateTime::parse_from_str(&t, "%Y-%m-%dT%H:%M").ok());
+        AuditFilter {
+            team: non_empty(&self.team),
+            attendee: non_empty(&self.attendee),
+            from: parse_time(&self.from),
+            to: parse_time(&self.to)
+        }
+    }
+}
+
+/**
+ * Most entries shown at once, narrow the filters to see older ones
+ */
+const MAX_ENTRIES: u32 = 500;
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AuditEntry {
+    pub created_at: String,
+    pub actor: String,
+    pub action: String,
+    pub team_name: Option<String>,
+    pub team_uuid: Option<String>,
+    pub attendee_name: Option<String>,
+    pub attendee_ref: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub details: String
+}
+
+/**
+ * Entries of the event matching the filter, most recent first
+ */
+pub async fn search(db: &mut MySqlConnection, event: &EventConfig, filter: &AuditFilter) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    let mut stmt = String::from(
+        "SELECT DATE_FORMAT(l.created_at, '%Y-%m-%d %H:%i:%s'), l.actor, l.action, t.name, t.uuid,
+            CONCAT(a.first_name, ' ', a.last_name), CONCAT(o.order_reference, '-', a.reference_index),
+            l.before_value, l.after_value, l.details
+        FROM audit_log l
+        LEFT JOIN teams t ON l.team_id = t.id
+        LEFT JOIN attendees a ON l.attendee_id = a.id
+        LEFT JOIN orders o ON a.order_id = o.id
+        WHERE l.event_id = ?"
+    );
+    if filter.team.is_some() {
+        stmt.push_str(" AND t.uuid = ?");
+    }
+    if filter.attendee.is_some() {
+        stmt.push_str(" AND CONCAT(o.order_reference, '-', a.reference_index) = ?");
+    }
+    if filter.from.is_some() {
+        stmt.push_str(" AND l.created_at >= ?");
+    }
+    if filter.to.is_some() {
+        stmt.push_str(" AND l.created_at <= ?");
+    }
+    stmt.push_str(&format!(" ORDER BY l.created_at DESC, l.id DESC LIMIT {MAX_ENTRIES}"));
+
+    let mut query = sqlx::query(&stmt).bind(event.event_id);
+    if let Some(team) = &filter.team {
+        query = query.bind(team);
+    }
+    if let Some(attendee) = &filter.attendee {
+        query = query.bind(attendee);
+    }
+    if let Some(from) = filter.from {
+        query = query.bind(from);
+    }
+    if let Some(to) = filter.to {
+        query = query.bind(to);
+    }
+
+    let entries = query.fetch_all(db)
+        .await?
+        .iter()
+        .map(|row| AuditEntry {
+            created_at: row.get(0),
+            actor: row.get(1),
+            action: row.get(2),
+            team_name: row.get(3),
+            team_uuid: row.get(4),
+            attendee_name: row.get(5),
+            attendee_ref: row.get(6),
+            before: row.get(7),
+            after: row.get(8),
+            details: row.get(9)
+        })
+        .collect();
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delegation_leaders_are_not_written_as_staff() {
+        assert_eq!(Actor::Staff(String::from("alice")).to_string(), "staff:alice");
+        assert_eq!(Actor::DelegationLeader(String::from("bob")).to_string(), "delegation:bob");
+    }
+
+    #[test]
+    fn every_actor_has_its_prefix() {
+        assert_eq!(Actor::Captain(12).to_string(), "captain:12");
+        assert_eq!(Actor::Attendee(String::from("ABC-1")).to_string(), "attendee:ABC-1");
+        assert_eq!(Actor::CheckIn { username: String::from("carol"), device: Some(String::from("phone")) }.to_string(), "checkin:carol@phone");
+        assert_eq!(Actor::CheckIn { username: String::from("carol"), device: None }.to_string(), "checkin:carol");
+        assert_eq!(Actor::System.to_string(), "system");
+    }
+}
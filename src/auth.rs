@@ -0,0 +1,329 @@
+//! Sessions of captains and staff members
+//!
+//! Sessions are kept in private cookies, which are encrypted with the
+//! secret_key of Rocket.toml and cannot be read nor forged by the browser
+
+use rand::Rng;
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+
+use rocket_db_pools::{sqlx, Connection};
+use rocket_db_pools::sqlx::mysql::MySqlConnection;
+
+use crate::Attendize;
+use crate::config::EventConfig;
+use crate::staff::{StaffAccount, StaffRole};
+use crate::audit::{Actor, Device};
+
+const CAPTAIN_COOKIE: &str = "captain";
+const STAFF_COOKIE: &str = "staff";
+const CSRF_COOKIE: &str = "csrf";
+
+/**
+ * Header in which pages send back the CSRF token of their session
+ */
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/**
+ * Who is sending the request, as proven by their session cookies
+ */
+pub struct Editor {
+    /**
+     * (event id, attendee id) of a captain who logged in
+     */
+    captain: Option<(u32, u32)>,
+    staff: Option<StaffAccount>
+}
+
+impl Editor {
+    /**
+     * Whether this session may modify the team : the sport admins of the
+     * event can edit every team, delegation leaders the teams of their school
+     * and a captain only their own teams
+     */
+    pub async fn can_edit(&self, db: &mut MySqlConnection, event: &EventConfig, uuid: &str) -> bool {
+        if self.is_staff(event) {
+            return true;
+        }
+        if let Some(school_id) = self.delegation_school(event) {
+            let team = sqlx::query("SELECT id FROM teams WHERE uuid = ? AND event_id = ? AND school_id = ? AND deleted_at IS NULL")
+                .bind(uuid)
+                .bind(event.event_id)
+                .bind(school_id)
+                .fetch_optional(&mut *db)
+                .await;
+            if matches!(team, Ok(Some(_))) {
+                return true;
+            }
+        }
+        match self.captain {
+            Some((event_id, attendee_id)) if event_id == event.event_id => {
+                let team = sqlx::query("SELECT id FROM teams WHERE uuid = ? AND event_id = ? AND captain_id = ? AND deleted_at IS NULL")
+                    .bind(uuid)
+                    .bind(event.event_id)
+                    .bind(attendee_id)
+                    .fetch_optional(db)
+                    .await;
+                matches!(team, Ok(Some(_)))
+            }
+            _ => false
+        }
+    }
+
+    pub fn is_staff(&self, event: &EventConfig) -> bool {
+        match &self.staff {
+            Some(account) => account.role == StaffRole::SportAdmin && account.can_access(event),
+            None => false
+        }
+    }
+
+    /**
+     * Who to record in the audit trail for a change allowed by can_edit,
+     * staff accounts come first when a captain session is also open
+     */
+    pub fn actor(&self, event: &EventConfig) -> Option<Actor> {
+        if let Some(account) = &self.staff {
+            if self.is_staff(event) {
+                return Some(Actor::Staff(account.username.clone()));
+            }
+            if self.delegation_school(event).is_some() {
+                return Some(Actor::DelegationLeader(account.username.clone()));
+            }
+        }
+        self.captain.map(|(_, attendee_id)| Actor::Captain(attendee_id))
+    }
+
+    fn delegation_school(&self, event: &EventConfig) -> Option<u32> {
+        match &self.staff {
+            Some(account) if account.role == StaffRole::DelegationLeader && account.can_access(event) => account.school_id,
+            _ => None
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Editor {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let cookies = request.cookies();
+        let captain = cookies.get_private(CAPTAIN_COOKIE).and_then(|c| {
+            let (event_id, attendee_id) = c.value().split_once(':')?;
+            Some((event_id.parse().ok()?, attendee_id.parse().ok()?))
+        });
+        let staff = session_account(request).await.clone();
+        Outcome::Success(Editor { captain, staff })
+    }
+}
+
+/**
+ * Opens a captain session, once they have proven who they are
+ */
+pub fn login_captain(cookies: &CookieJar<'_>, event: &EventConfig, attendee_id: u32) {
+    cookies.add_private(Cookie::new(CAPTAIN_COOKIE, format!("{}:{attendee_id}", event.event_id)));
+}
+
+/**
+ * Opens a staff session, once the password of the account was checked
+ */
+pub fn login_staff(cookies: &CookieJar<'_>, account: &StaffAccount) {
+    cookies.add_private(Cookie::new(STAFF_COOKIE, account.id.to_string()));
+}
+
+pub fn logout(cookies: &CookieJar<'_>) {
+    cookies.remove_private(Cookie::named(CAPTAIN_COOKIE));
+    cookies.remove_private(Cookie::named(STAFF_COOKIE));
+}
+
+/**
+ * CSRF token of the session, created on first use
+ *
+ * Pages embed it and send it back in the X-CSRF-Token header with every mutation
+ */
+pub fn csrf_token(cookies: &CookieJar<'_>) -> String {
+    if let Some(cookie) = cookies.get_private(CSRF_COOKIE) {
+        return String::from(cookie.value());
+    }
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    let token: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    cookies.add_private(Cookie::new(CSRF_COOKIE, token.clone()));
+    token
+}
+
+/**
+ * Request guard of every mutation, the X-CSRF-Token header must match the
+ * token of the session, which a third party page cannot read
+ */
+pub struct CsrfProtected;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CsrfProtected {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = request.cookies().get_private(CSRF_COOKIE);
+        let given = request.headers().get_one(CSRF_HEADER);
+        match (expected, given) {
+            (Some(cookie), Some(token)) if cookie.value() == token => Outcome::Success(CsrfProtected),
+            _ => Outcome::Failure((Status::Forbidden, ()))
+        }
+    }
+}
+
+/**
+ * Account of the staff session, looked up once per request
+ *
+ * Accounts are re-read from the database so that disabling one ends its sessions
+ */
+async fn session_account<'r>(request: &'r Request<'_>) -> &'r Option<StaffAccount> {
+    request.local_cache_async(async {
+        let id: u32 = request.cookies().get_private(STAFF_COOKIE)?.value().parse().ok()?;
+        let mut db = request.guard::<Connection<Attendize>>().await.succeeded()?;
+        StaffAccount::find_active(&mut db, id).await
+    }).await
+}
+
+/**
+ * Staff session with one of the given roles, 401 without a session and 403 with another role
+ */
+async fn staff_with_role(request: &Request<'_>, roles: &[StaffRole]) -> Outcome<StaffAccount, ()> {
+    match session_account(request).await {
+        Some(account) if account.has_role(roles) => Outcome::Success(account.clone()),
+        Some(_) => Outcome::Failure((Status::Forbidden, ())),
+        None => Outcome::Failure((Status::Unauthorized, ()))
+    }
+}
+
+/**
+ * Staff allowed to see team lists : sport admins and read only accounts
+ */
+pub struct SportStaff(pub StaffAccount);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SportStaff {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        staff_with_role(request, &[StaffRole::SportAdmin, StaffRole::ReadOnly]).await.map(SportStaff)
+    }
+}
+
+/**
+ * Staff allowed to change teams, the configuration and staff accounts
+ */
+pub struct SportAdmin(pub StaffAccount);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SportAdmin {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        staff_with_role(request, &[StaffRole::SportAdmin]).await.map(SportAdmin)
+    }
+}
+
+/**
+ * Staff allowed to use the scan app, read only accounts look tickets up without checking them in
+ */
+pub struct CheckInStaff {
+    pub account: StaffAccount,
+    pub device: Option<String>
+}
+
+impl CheckInStaff {
+    pub fn can_check_in(&self) -> bool {
+        self.account.has_role(&[StaffRole::SportAdmin, StaffRole::CheckInAgent])
+    }
+
+    pub fn actor(&self) -> Actor {
+        Actor::CheckIn { username: self.account.username.clone(), device: self.device.clone() }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CheckInStaff {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let account = match staff_with_role(request, &[StaffRole::SportAdmin, StaffRole::CheckInAgent, StaffRole::ReadOnly]).await {
+            Outcome::Success(account) => account,
+            Outcome::Failure(f) => return Outcome::Failure(f),
+            Outcome::Forward(f) => return Outcome::Forward(f)
+        };
+        let device = request.guard::<Device>().await.succeeded().and_then(|d| d.0);
+        Outcome::Success(CheckInStaff { account, device })
+    }
+}
+
+/**
+ * Staff allowed to validate scanned tickets, with the scan app device they use
+ */
+pub struct ScanningStaff {
+    pub account: StaffAccount,
+    pub device: Option<String>
+}
+
+impl ScanningStaff {
+    pub fn actor(&self) -> Actor {
+        Actor::CheckIn { username: self.account.username.clone(), device: self.device.clone() }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ScanningStaff {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let account = match staff_with_role(request, &[StaffRole::SportAdmin, StaffRole::CheckInAgent]).await {
+            Outcome::Success(account) => account,
+            Outcome::Failure(f) => return Outcome::Failure(f),
+            Outcome::Forward(f) => return Outcome::Forward(f)
+        };
+        let device = request.guard::<Device>().await.succeeded().and_then(|d| d.0);
+        Outcome::Success(ScanningStaff { account, device })
+    }
+}
+
+/**
+ * Scanning staff sending a change from the scan app or a food stand,
+ * which also needs the CSRF token of the session
+ */
+pub struct ScanningStaffChange(pub ScanningStaff);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ScanningStaffChange {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let staff = match request.guard::<ScanningStaff>().await {
+            Outcome::Success(staff) => staff,
+            Outcome::Failure(f) => return Outcome::Failure(f),
+            Outcome::Forward(f) => return Outcome::Forward(f)
+        };
+        request.guard::<CsrfProtected>().await.map(|_| ScanningStaffChange(staff))
+    }
+}
+
+/**
+ * Delegation leader, bound to the school of their account
+ */
+pub struct DelegationLeader {
+    pub account: StaffAccount,
+    pub school_id: u32
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DelegationLeader {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match staff_with_role(request, &[StaffRole::DelegationLeader]).await {
+            Outcome::Success(account) => match account.school_id {
+                Some(school_id) => Outcome::Success(DelegationLeader { account, school_id }),
+                None => Outcome::Failure((Status::Forbidden, ()))
+            },
+            Outcome::Failure(f) => Outcome::Failure(f),
+            Outcome::Forward(f) => Outcome::Forward(f)
+        }
+    }
+}
@@ -0,0 +1,634 @@
+//! Corrections of the check-in, for tickets that were scanned by mistake, and
+//! offline check-in : a device downloads a signed snapshot of the attendees,
+//! checks tickets in without network and sends its scans in a batch later
+
+use std::collections::{HashMap, HashSet};
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use rocket::figment::Figment;
+use rocket::serde::{Serialize, Deserialize};
+use rocket::serde::json::serde_json;
+
+use rocket_db_pools::sqlx;
+use rocket_db_pools::sqlx::Row;
+use rocket_db_pools::sqlx::mysql::MySqlConnection;
+use rocket_db_pools::sqlx::types::chrono::{Local, NaiveDateTime};
+
+use crate::config::{EventConfig, TicketEntitlements};
+use crate::audit::{self, Actor, Change};
+use crate::defs::SimpleResponseCode;
+use crate::teams::in_transaction;
+
+/**
+ * Longest reason kept in the audit log
+ */
+const MAX_REASON_LENGTH: usize = 255;
+
+/**
+ * Most scans sent in one batch, a device syncs again for the rest
+ */
+const MAX_BATCH_SCANS: usize = 1000;
+
+/**
+ * How far the clock of a device may be from the server clock, scans further
+ * before the snapshot or in the future are refused
+ */
+const MAX_CLOCK_DRIFT_SECONDS: i64 = 300;
+
+/**
+ * Format of scan times sent by the devices, in the server's local time
+ * example : 2023-05-12 14:03:22
+ */
+const SCAN_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/**
+ * Reasons why a check-in could not be undone
+ */
+pub enum UndoCheckInError {
+    ReadOnly,
+    MissingReason,
+    UnknownTicket,
+    /**
+     * Name of the attendee
+     */
+    NotCheckedIn(String),
+    Database(String)
+}
+
+impl UndoCheckInError {
+    pub fn message(&self) -> String {
+        match self {
+            UndoCheckInError::ReadOnly => String::from("Check-in is read only"),
+            UndoCheckInError::MissingReason => format!("Give the reason of the correction, in at most {MAX_REASON_LENGTH} characters"),
+            UndoCheckInError::UnknownTicket => String::from("Ticket not found"),
+            UndoCheckInError::NotCheckedIn(name) => format!("{name} is not checked in"),
+            UndoCheckInError::Database(e) => format!("DB error, rolling back : {e}")
+        }
+    }
+}
+
+/**
+ * Check-in that was undone
+ */
+pub struct UndoneCheckIn {
+    pub name: String,
+    /**
+     * Who scanned the ticket, as written in the audit log, None when the
+     * check-in was made before the audit log or directly in Attendize
+     */
+    pub scanned_by: Option<String>
+}
+
+/**
+ * Puts the attendee back to not arrived, reference is the private_reference_number
+ * of the QR code
+ *
+ * The undo is logged with the reason, the staff account and device undoing it,
+ * and the account and device of the scan being undone
+ */
+pub async fn undo_check_in(db: &mut MySqlConnection, event: &EventConfig, reference: &str, reason: &str, actor: &Actor) -> Result<UndoneCheckIn, UndoCheckInError> {
+    if event.check_in_read_only {
+        return Err(UndoCheckInError::ReadOnly);
+    }
+    let reason = reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_LENGTH {
+        return Err(UndoCheckInError::MissingReason);
+    }
+    in_transaction(db, UndoCheckInError::Database, |tx| Box::pin(reset_arrival(tx, event, reference, reason, actor))).await
+}
+
+async fn reset_arrival(tx: &mut MySqlConnection, event: &EventConfig, reference: &str, reason: &str, actor: &Actor) -> Result<UndoneCheckIn, UndoCheckInError> {
+    let db_error = |e: sqlx::Error| UndoCheckInError::Database(e.to_string());
+
+    // Locked so that a scan of the same ticket waits for the undo
+    let attendee = sqlx::query(
+        "SELECT id, has_arrived, DATE_FORMAT(arrival_time, '%Y-%m-%d %H:%i:%s'), CONCAT(first_name, ' ', last_name) FROM attendees
+        WHERE event_id = ? AND private_reference_number = ? FOR UPDATE"
+    )
+    .bind(event.event_id)
+    .bind(reference)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .ok_or(UndoCheckInError::UnknownTicket)?;
+    let attendee_id: u32 = attendee.get(0);
+    let has_arrived: bool = attendee.get(1);
+    let arrival_time: Option<String> = attendee.get(2);
+    let name: String = attendee.get(3);
+    if !has_arrived {
+        return Err(UndoCheckInError::NotCheckedIn(name));
+    }
+
+    let scanned_by: Option<String> = sqlx::query(
+        "SELECT actor FROM audit_log WHERE event_id = ? AND attendee_id = ? AND action = 'check_in' ORDER BY id DESC LIMIT 1"
+    )
+    .bind(event.event_id)
+    .bind(attendee_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .map(|row| row.get(0));
+
+    sqlx::query("UPDATE attendees SET has_arrived = 0, arrival_time = NULL WHERE id = ?")
+        .bind(attendee_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    let before = match &arrival_time {
+        Some(time) => format!("arrived at {time}"),
+        None => String::from("arrived")
+    };
+    let change = Change {
+        attendee_id: Some(attendee_id),
+        before: Some(before),
+        after: Some(String::from("not arrived")),
+        details: format!("scanned by {}, reason : {reason}", scanned_by.as_deref().unwrap_or("unknown")),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "undo_check_in", change).await.map_err(db_error)?;
+    Ok(UndoneCheckIn { name, scanned_by })
+}
+
+/**
+ * Key signing the check-in snapshots, derived from the secret_key of Rocket.toml
+ * so that the key of the session cookies never signs anything else
+ *
+ * Without a secret_key a random key is used, snapshots then stop being
+ * accepted when the server restarts, like sessions
+ */
+pub struct SnapshotKey(Vec<u8>);
+
+impl SnapshotKey {
+    pub fn from_figment(figment: &Figment) -> SnapshotKey {
+        match figment.extract_inner::<String>("secret_key") {
+            Ok(secret) => SnapshotKey::derive(secret.as_bytes()),
+            Err(_) => {
+                warn!("No secret_key configured, check-in snapshots will not be accepted after a restart");
+                let key: [u8; 32] = rand::thread_rng().gen();
+                SnapshotKey(key.to_vec())
+            }
+        }
+    }
+
+    /**
+     * HMAC(secret, "checkin-snapshot")
+     */
+    fn derive(secret: &[u8]) -> SnapshotKey {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC key");
+        mac.update(b"checkin-snapshot");
+        SnapshotKey(mac.finalize().into_bytes().to_vec())
+    }
+
+    fn mac(&self, header: &SnapshotHeader) -> Hmac<Sha256> {
+        // Hmac accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC key");
+        mac.update(format!("{}|{}|{}|{}", header.event, header.device, header.generated_at, header.digest).as_bytes());
+        mac
+    }
+
+    fn sign(&self, header: &SnapshotHeader) -> String {
+        to_hex(&self.mac(header).finalize().into_bytes())
+    }
+
+    /**
+     * Whether the snapshot was issued by this server, compared in constant time
+     */
+    fn verify(&self, header: &SnapshotHeader) -> bool {
+        match from_hex(&header.signature) {
+            Some(signature) => self.mac(header).verify_slice(&signature).is_ok(),
+            None => false
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/**
+ * Identifies a snapshot, signed with the SnapshotKey
+ *
+ * The digest is the SHA-256 of the entitlements and attendees of the snapshot,
+ * the device sends the header back with its scans
+ */
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct SnapshotHeader {
+    pub event: String,
+    pub device: String,
+    pub generated_at: String,
+    pub digest: String,
+    pub signature: String
+}
+
+/**
+ * An attendee as the scan app needs it to check them in without network
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SnapshotAttendee {
+    /**
+     * private_reference_number, the content of the QR code
+     */
+    pub reference: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub school: Option<String>,
+    pub sports: Vec<String>,
+    pub ticket_title: String,
+    pub cancelled: bool,
+    pub arrival_time: Option<String>,
+    /**
+     * Name of the entitlements of the ticket, None when no section lists it
+     */
+    pub entitlements: Option<String>
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CheckInSnapshot {
+    pub snapshot: SnapshotHeader,
+    pub entitlements: Vec<TicketEntitlements>,
+    pub attendees: Vec<SnapshotAttendee>
+}
+
+/**
+ * Every attendee of the event with the entitlements of their ticket, signed for the device
+ */
+pub async fn snapshot(db: &mut MySqlConnection, event: &EventConfig, key: &SnapshotKey, device: &str) -> Result<CheckInSnapshot, sqlx::Error> {
+    let mut sports: HashMap<u32, Vec<String>> = HashMap::new();
+    let sports_stmt = format!(
+        "SELECT DISTINCT qa.attendee_id, qa.answer_text FROM question_answers qa
+        JOIN attendees a ON qa.attendee_id = a.id
+        WHERE a.event_id = ? AND qa.question_id IN {}",
+        event.all_sport_question_ids()
+    );
+    for row in sqlx::query(&sports_stmt).bind(event.event_id).fetch_all(&mut *db).await? {
+        sports.entry(row.get(0)).or_default().push(row.get(1));
+    }
+
+    let attendees = sqlx::query(
+        "SELECT a.id, CAST(a.private_reference_number AS CHAR), a.first_name, a.last_name, qs.answer_text, t.title, a.ticket_id,
+            a.is_cancelled, DATE_FORMAT(a.arrival_time, '%Y-%m-%d %H:%i:%s'), a.has_arrived
+        FROM attendees a
+        JOIN tickets t ON a.ticket_id = t.id
+        LEFT JOIN question_answers qs ON qs.attendee_id = a.id AND qs.question_id = ?
+        WHERE a.event_id = ?
+        ORDER BY a.id"
+    )
+    .bind(event.school_question_id)
+    .bind(event.event_id)
+    .fetch_all(&mut *db)
+    .await?
+    .iter()
+    .map(|row| {
+        let has_arrived: bool = row.get(9);
+        let arrival_time: Option<String> = row.get(8);
+        SnapshotAttendee {
+            reference: row.get(1),
+            first_name: row.get(2),
+            last_name: row.get(3),
+            school: row.get(4),
+            sports: sports.remove(&row.get::<u32, _>(0)).unwrap_or_default(),
+            ticket_title: row.get(5),
+            cancelled: row.get(7),
+            // Arrived without a time, shown as arrived all the same
+            arrival_time: if has_arrived { Some(arrival_time.unwrap_or_default()) } else { None },
+            entitlements: event.ticket_entitlements(row.get(6)).map(|e| e.name.clone())
+        }
+    })
+    .collect::<Vec<SnapshotAttendee>>();
+
+    let entitlements = event.entitlements.clone();
+    let content = serde_json::to_string(&(&entitlements, &attendees)).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    let mut header = SnapshotHeader {
+        event: event.slug.clone(),
+        device: String::from(device),
+        generated_at: now().format(SCAN_TIME_FORMAT).to_string(),
+        digest: to_hex(&Sha256::digest(content.as_bytes())),
+        signature: String::new()
+    };
+    header.signature = key.sign(&header);
+    Ok(CheckInSnapshot { snapshot: header, entitlements, attendees })
+}
+
+/**
+ * A ticket checked in by a device without network
+ */
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OfflineScan {
+    pub reference: String,
+    /**
+     * Clock of the device, example : 2023-05-12 14:03:22
+     */
+    pub scanned_at: String
+}
+
+/**
+ * Scans queued by a device, with the header of the snapshot it checked them against
+ */
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SyncBatch {
+    pub snapshot: SnapshotHeader,
+    pub scans: Vec<OfflineScan>
+}
+
+/**
+ * What became of an offline scan
+ *
+ * The earliest scan of a ticket always wins, whatever the order in which
+ * the devices sync, so every gate ends up with the same arrival time
+ */
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum SyncStatus {
+    CheckedIn,
+    /**
+     * The ticket was checked in later by another scan, the arrival time is moved back to this scan
+     */
+    EarlierScan,
+    /**
+     * This very scan was already synced, the device sent it again
+     */
+    AlreadySynced,
+    /**
+     * The ticket was checked in before this scan, which is ignored
+     */
+    AlreadyCheckedIn,
+    Cancelled,
+    UnknownTicket,
+    /**
+     * The ticket was scanned earlier in the same batch
+     */
+    Duplicate,
+    /**
+     * Scan time unreadable, before the snapshot or in the future
+     */
+    InvalidTime
+}
+
+impl SyncStatus {
+    /**
+     * Whether staff should look at the scan
+     */
+    pub fn is_conflict(&self) -> bool {
+        !matches!(self, SyncStatus::CheckedIn | SyncStatus::AlreadySynced)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SyncResult {
+    pub reference: String,
+    pub scanned_at: String,
+    pub status: SyncStatus,
+    pub name: Option<String>,
+    pub detail: String
+}
+
+/**
+ * Answer to a batch, with the scans that staff should look at counted as conflicts
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SyncReport {
+    pub message: String,
+    pub code: SimpleResponseCode,
+    pub checked_in: usize,
+    pub conflicts: usize,
+    pub results: Vec<SyncResult>
+}
+
+/**
+ * Reasons why a whole batch was refused
+ */
+pub enum SyncError {
+    ReadOnly,
+    InvalidSnapshot,
+    /**
+     * Older than snapshot_max_age_hours, with that age
+     */
+    ExpiredSnapshot(u32),
+    /**
+     * The snapshot was issued to another device than the one syncing
+     */
+    OtherDevice,
+    TooManyScans,
+    Database(String)
+}
+
+impl SyncError {
+    pub fn message(&self) -> String {
+        match self {
+            SyncError::ReadOnly => String::from("Check-in is read only"),
+            SyncError::InvalidSnapshot => String::from("This snapshot was not issued for this event by this server, download a new one"),
+            SyncError::ExpiredSnapshot(hours) => format!("This snapshot is more than {hours} hours old, download a new one"),
+            SyncError::OtherDevice => String::from("This snapshot was issued to another device, download a new one on this device"),
+            SyncError::TooManyScans => format!("Send at most {MAX_BATCH_SCANS} scans at once"),
+            SyncError::Database(e) => format!("DB error, rolling back : {e}")
+        }
+    }
+}
+
+/**
+ * Checks in the scans of a device, in the order in which they were made,
+ * device is the one sending them, which must be the one of the snapshot
+ *
+ * Every scan has its own transaction : when the batch fails halfway, the device
+ * sends it again and the scans already synced are reported as such
+ */
+pub async fn sync(db: &mut MySqlConnection, event: &EventConfig, key: &SnapshotKey, batch: &SyncBatch, username: &str, device: Option<&str>) -> Result<Vec<SyncResult>, SyncError> {
+    if event.check_in_read_only {
+        return Err(SyncError::ReadOnly);
+    }
+    if batch.snapshot.event != event.slug || !key.verify(&batch.snapshot) {
+        return Err(SyncError::InvalidSnapshot);
+    }
+    if batch.scans.len() > MAX_BATCH_SCANS {
+        return Err(SyncError::TooManyScans);
+    }
+    // A leaked snapshot cannot be replayed from another device
+    if device != Some(batch.snapshot.device.as_str()) {
+        return Err(SyncError::OtherDevice);
+    }
+    let generated_at = NaiveDateTime::parse_from_str(&batch.snapshot.generated_at, SCAN_TIME_FORMAT)
+        .map_err(|_| SyncError::InvalidSnapshot)?;
+    let now = now();
+    if (now - generated_at).num_seconds() > i64::from(event.snapshot_max_age_hours) * 3600 {
+        return Err(SyncError::ExpiredSnapshot(event.snapshot_max_age_hours));
+    }
+    let actor = Actor::CheckIn { username: String::from(username), device: Some(batch.snapshot.device.clone()) };
+
+    let mut scans: Vec<(Option<NaiveDateTime>, &OfflineScan)> = batch.scans.iter()
+        .map(|scan| (NaiveDateTime::parse_from_str(&scan.scanned_at, SCAN_TIME_FORMAT).ok(), scan))
+        .collect();
+    // Unreadable times first, then the earliest scans, scans made at the same time
+    // in the order of their reference so that the outcome does not depend on the batch order
+    scans.sort_by(|(a, sa), (b, sb)| a.cmp(b).then_with(|| sa.reference.cmp(&sb.reference)));
+
+    let mut results = vec![];
+    let mut seen: HashSet<&str> = HashSet::new();
+    for (scanned_at, scan) in scans {
+        let result = |status: SyncStatus, detail: &str| SyncResult {
+            reference: scan.reference.clone(),
+            scanned_at: scan.scanned_at.clone(),
+            status,
+            name: None,
+            detail: String::from(detail)
+        };
+        let scanned_at = match scanned_at {
+            Some(t) if (generated_at - t).num_seconds() <= MAX_CLOCK_DRIFT_SECONDS && (t - now).num_seconds() <= MAX_CLOCK_DRIFT_SECONDS => t,
+            _ => {
+                results.push(result(SyncStatus::InvalidTime, "the scan time is before the snapshot, in the future or unreadable"));
+                continue;
+            }
+        };
+        if !seen.insert(&scan.reference) {
+            results.push(result(SyncStatus::Duplicate, "scanned earlier in the same batch"));
+            continue;
+        }
+
+        let actor = &actor;
+        let synced = in_transaction(&mut *db, SyncError::Database, |tx| Box::pin(async move {
+            sync_scan(tx, event, &scan.reference, scanned_at, actor).await.map_err(|e| SyncError::Database(e.to_string()))
+        })).await?;
+        match synced {
+            Some((status, name, detail)) => results.push(SyncResult { name: Some(name), ..result(status, &detail) }),
+            None => results.push(result(SyncStatus::UnknownTicket, "no ticket of the event has this reference"))
+        }
+    }
+    Ok(results)
+}
+
+/**
+ * Applies one offline scan, None when the ticket is unknown
+ */
+async fn sync_scan(tx: &mut MySqlConnection, event: &EventConfig, reference: &str, scanned_at: NaiveDateTime, actor: &Actor) -> Result<Option<(SyncStatus, String, String)>, sqlx::Error> {
+    // Locked so that two devices syncing the same ticket are handled one after the other
+    let attendee = match sqlx::query(
+        "SELECT id, is_cancelled, has_arrived, arrival_time, CONCAT(first_name, ' ', last_name) FROM attendees
+        WHERE event_id = ? AND private_reference_number = ? FOR UPDATE"
+    )
+    .bind(event.event_id)
+    .bind(reference)
+    .fetch_optional(&mut *tx)
+    .await? {
+        Some(a) => a,
+        None => return Ok(None)
+    };
+    let attendee_id: u32 = attendee.get(0);
+    let is_cancelled: bool = attendee.get(1);
+    let has_arrived: bool = attendee.get(2);
+    let arrival_time: Option<NaiveDateTime> = attendee.get(3);
+    let name: String = attendee.get(4);
+    let scan_time = scanned_at.format(SCAN_TIME_FORMAT).to_string();
+
+    let (status, detail) = if is_cancelled {
+        (SyncStatus::Cancelled, String::from("the ticket is cancelled"))
+    }
+    else if !has_arrived {
+        set_arrival(&mut *tx, attendee_id, scanned_at).await?;
+        let change = Change {
+            attendee_id: Some(attendee_id),
+            before: Some(String::from("not arrived")),
+            after: Some(String::from("arrived")),
+            details: format!("offline scan at {scan_time}"),
+            ..Default::default()
+        };
+        audit::record(&mut *tx, event, actor, "check_in", change).await?;
+        (SyncStatus::CheckedIn, String::new())
+    }
+    else {
+        match arrival_time {
+            Some(time) if time == scanned_at => (SyncStatus::AlreadySynced, String::new()),
+            Some(time) if time > scanned_at => {
+                set_arrival(&mut *tx, attendee_id, scanned_at).await?;
+                let change = Change {
+                    attendee_id: Some(attendee_id),
+                    before: Some(format!("arrived at {}", time.format(SCAN_TIME_FORMAT))),
+                    after: Some(format!("arrived at {scan_time}")),
+                    details: String::from("offline scan earlier than the recorded check-in"),
+                    ..Default::default()
+                };
+                audit::record(&mut *tx, event, actor, "check_in", change).await?;
+                (SyncStatus::EarlierScan, format!("arrival time moved back from {}", time.format(SCAN_TIME_FORMAT)))
+            }
+            Some(time) => (SyncStatus::AlreadyCheckedIn, format!("already checked in at {}", time.format(SCAN_TIME_FORMAT))),
+            None => (SyncStatus::AlreadyCheckedIn, String::from("already checked in"))
+        }
+    };
+
+    if status.is_conflict() && status != SyncStatus::EarlierScan {
+        let change = Change {
+            attendee_id: Some(attendee_id),
+            details: format!("offline scan at {scan_time} ignored : {detail}"),
+            ..Default::default()
+        };
+        audit::record(&mut *tx, event, actor, "check_in_conflict", change).await?;
+    }
+    Ok(Some((status, name, detail)))
+}
+
+async fn set_arrival(tx: &mut MySqlConnection, attendee_id: u32, scanned_at: NaiveDateTime) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE attendees SET has_arrived = 1, arrival_time = ? WHERE id = ?")
+        .bind(scanned_at)
+        .bind(attendee_id)
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
+/**
+ * Current time of the server, scans are stamped with the local time of the devices
+ */
+fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> SnapshotHeader {
+        SnapshotHeader {
+            event: String::from("games"),
+            device: String::from("phone"),
+            generated_at: String::from("2023-05-12 08:00:00"),
+            digest: String::from("00"),
+            signature: String::new()
+        }
+    }
+
+    #[test]
+    fn snapshot_key_is_not_the_secret_key() {
+        let secret = b"secret of the session cookies";
+        let key = SnapshotKey::derive(secret);
+        assert_ne!(key.0, secret.to_vec());
+
+        let mut signed = header();
+        signed.signature = SnapshotKey(secret.to_vec()).sign(&signed);
+        assert!(!key.verify(&signed));
+    }
+
+    #[test]
+    fn snapshot_signed_with_derived_key_is_accepted() {
+        let key = SnapshotKey::derive(b"secret of the session cookies");
+        let mut signed = header();
+        signed.signature = key.sign(&signed);
+        assert!(key.verify(&signed));
+        assert!(SnapshotKey::derive(b"secret of the session cookies").verify(&signed));
+    }
+}
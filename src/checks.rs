@@ -3,7 +3,7 @@ use rocket_db_pools::sqlx::Row;
 use rocket_db_pools::sqlx::mysql::{MySqlConnection, MySqlRow};
 
 use crate::defs::*;
-use crate::config;
+use crate::config::EventConfig;
 
 /**
  * Retrieves an Option<IdentifiedAttendee> from the attendee's order_ref
@@ -11,92 +11,77 @@ use crate::config;
  * order_ref : &str
  *  example : 'hGsddrf-1'
  */
-pub async fn retrieve_attendee(db: &mut MySqlConnection, order_ref:&str) -> Result<IdentifiedAttendee, String> {
+pub async fn retrieve_attendee(db: &mut MySqlConnection, event: &EventConfig, order_ref:&str) -> Result<IdentifiedAttendee, String> {
     if order_ref.len() > 12
     {
-        return Err(format!("Order reference invalid"));
+        Err(String::from("Order reference invalid"))
     }
     else {
         let iter = order_ref.split('-');
         let split_ref = iter.collect::<Vec<&str>>();
         if split_ref.len() != 2 {
-            return Err(format!("Order reference invalid"));
+            return Err(String::from("Order reference invalid"));
         }
         // println!("order_ref : {}, index : {}", split_ref[0], split_ref[1]);
         // Retrieve attendee_id, ticket id and gender (one row only)
         let attendee_stmt = format!("SELECT a.id, a.ticket_id, qa.answer_text
         FROM orders o, attendees a, question_answers qa
-        WHERE o.event_id = 2
+        WHERE o.event_id = {}
         AND qa.attendee_id = a.id
         AND qa.question_id = {}
         AND a.is_cancelled = 0
         AND o.id = a.order_id
         AND o.order_reference = ?
-        AND a.reference_index = ?", config::get_option("gender_question_id"));
+        AND a.reference_index = ?", event.event_id, event.gender_question_id);
 
         let attendee_res = sqlx::query(&attendee_stmt).bind(split_ref[0]).bind(split_ref[1])
         .fetch_optional(&mut *db).await;
 
-        let first_row:MySqlRow;
-
-        match attendee_res {
+        let first_row:MySqlRow = match attendee_res {
             Ok(ro) => {
                 match ro {
-                    Some(r) => first_row = r,
-                    None => return Err(format!("Attendee not found"))
+                    Some(r) => r,
+                    None => return Err(String::from("Attendee not found"))
                 }
             }
             Err(e) => return Err(format!("Error while retrieving attendee : {e}"))
-        }
+        };
 
         let attendee_id:u32 = first_row.get(0);
         let ticket_id:u32 = first_row.get(1);
         
         let gender_name:String = first_row.get(2);
 
-        complete_attendee(&mut *db, attendee_id, ticket_id, gender_name).await
+        complete_attendee(&mut *db, event, attendee_id, ticket_id, gender_name).await
     }
 }
-pub async fn get_attendee(db:&mut MySqlConnection, attendee_id:u32) -> Result<IdentifiedAttendee, String> {
+pub async fn get_attendee(db:&mut MySqlConnection, event: &EventConfig, attendee_id:u32) -> Result<IdentifiedAttendee, String> {
     let attendee_stmt = format!("SELECT a.ticket_id, qa.answer_text
         FROM attendees a, question_answers qa
         WHERE qa.attendee_id = a.id
         AND qa.question_id = {}
         AND a.is_cancelled = 0
-        AND a.id = ?", config::get_option("gender_question_id"));
+        AND a.id = ?", event.gender_question_id);
     let res = sqlx::query(&attendee_stmt).bind(attendee_id).fetch_optional(&mut *db).await;
-    if res.is_err() {
-        return Err(format!("SQL error while getting attendee"));
-    }
-    match res.unwrap() {
-        Some(r) => {
-            let ticket_id:u32;
-            let gender_name:String;
-            ticket_id = r.get(0);
-            gender_name = r.get(1);
-            return complete_attendee(&mut *db, attendee_id, ticket_id, gender_name).await;
-        }
-        None => {
-            return Err(format!("Attendee not found"));
+    match res {
+        Ok(Some(r)) => {
+            let ticket_id:u32 = r.get(0);
+            let gender_name:String = r.get(1);
+            complete_attendee(&mut *db, event, attendee_id, ticket_id, gender_name).await
         }
+        Ok(None) => Err(String::from("Attendee not found")),
+        Err(_) => Err(String::from("SQL error while getting attendee"))
     }
 }
-pub async fn complete_attendee(db:&mut MySqlConnection, attendee_id:u32, ticket_id:u32, gender_name:String) -> Result<IdentifiedAttendee, String> {
-    let gender:AttendeeGender;
-
-    match gender_name.as_str() {
-        "Male" => gender = AttendeeGender::M,
-        "Female" => gender = AttendeeGender::F,
+pub async fn complete_attendee(db:&mut MySqlConnection, event: &EventConfig, attendee_id:u32, ticket_id:u32, gender_name:String) -> Result<IdentifiedAttendee, String> {
+    let gender:AttendeeGender = match gender_name.as_str() {
+        "Male" => AttendeeGender::M,
+        "Female" => AttendeeGender::F,
         other => return Err(format!("Gender name unknown \'{other}\'"))
-    }
+    };
     // Get attendee sports
     // Ensure the correct sports are made available
-    let sport_question_ids:String;
-
-    match gender {
-        AttendeeGender::M => sport_question_ids = config::get_option("male_sport_question_ids"),
-        AttendeeGender::F => sport_question_ids = config::get_option("female_sport_question_ids")
-    }
+    let sport_question_ids = event.sport_question_ids(gender);
 
     let sports_stmt = format!(
         "SELECT answer_text FROM question_answers
@@ -106,19 +91,42 @@ pub async fn complete_attendee(db:&mut MySqlConnection, attendee_id:u32, ticket_
     let sports_fut = sqlx::query(&sports_stmt).bind(attendee_id)
     .fetch_all(&mut *db);
 
-    let sports_res = sports_fut.await;
-    if sports_res.is_err() {
-        return Err(format!("SQL error while retrieving attendee sports"));
-    }
+    let sports_rows = match sports_fut.await {
+        Ok(rows) => rows,
+        Err(_) => return Err(String::from("SQL error while retrieving attendee sports"))
+    };
 
     let mut sports:Vec<Sport> = Vec::new();
 
-    for row in sports_res.unwrap() {
+    for row in sports_rows {
         let sport_name:String = row.get(0);
         
-        let sport = config::find_sport(sport_name.as_str(), Some(gender));
-        if sport.is_ok() { //Ignore sports that are not in the config file (individual sports)
-            sports.push(sport.unwrap());
+        //Ignore sports that are not in the config file (individual sports)
+        if let Ok(sport) = event.find_sport(sport_name.as_str(), Some(gender)) {
+            sports.push(sport);
+        }
+    }
+
+    // Sports with a capacity are not answered in Attendize, the athlete has them once their spot is confirmed
+    let signups_fut = sqlx::query(
+        "SELECT sport FROM sport_signups WHERE event_id = ? AND attendee_id = ? AND status = 'confirmed'"
+    )
+    .bind(event.event_id)
+    .bind(attendee_id)
+    .fetch_all(&mut *db);
+
+    let signup_rows = match signups_fut.await {
+        Ok(rows) => rows,
+        Err(_) => return Err(String::from("SQL error while retrieving attendee sign-ups"))
+    };
+
+    for row in signup_rows {
+        let sport_name:String = row.get(0);
+        if has_sport_named(&sports, sport_name.as_str()) {
+            continue;
+        }
+        if let Ok(sport) = event.find_sport(sport_name.as_str(), Some(gender)) {
+            sports.push(sport);
         }
     }
 
@@ -126,39 +134,39 @@ pub async fn complete_attendee(db:&mut MySqlConnection, attendee_id:u32, ticket_
         "SELECT qo.id FROM question_options qo
         JOIN question_answers qa ON qa.question_id = qo.question_id
         WHERE qa.question_id = {} AND qa.attendee_id = ? AND qo.name = qa.answer_text",
-        config::get_option("school_question_id")
+        event.school_question_id
     );
 
     let school_fut = sqlx::query(&school_stmt).bind(attendee_id)
     .fetch_one(&mut *db);
 
-    let school_res = school_fut.await;
-    let school_id: u32;
-    if school_res.is_err() {
-        return Err(format!("SQL error while retrieving attendee school"));
-    }
-    else {
-        school_id = school_res.unwrap().get(0);
-    }
+    let school_id: u32 = match school_fut.await {
+        Ok(row) => row.get(0),
+        Err(_) => return Err(String::from("SQL error while retrieving attendee school"))
+    };
 
-    return Ok(IdentifiedAttendee { 
+    Ok(IdentifiedAttendee { 
         id: attendee_id, 
-        ticket_id: ticket_id, 
-        gender: gender, 
-        sports: sports,
-        school_id: school_id});
+        ticket_id, 
+        gender, 
+        sports,
+        school_id})
 }
 
 /**
- * Checks if the attendee registered in the team's sport
+ * Checks if the attendee registered in the team's sport, in Attendize or with a confirmed sign-up
  */
 pub fn has_sport(attendee:&IdentifiedAttendee, sport_name:&str) -> bool {
-    for sport in &attendee.sports {
+    has_sport_named(&attendee.sports, sport_name)
+}
+
+fn has_sport_named(sports:&[Sport], sport_name:&str) -> bool {
+    for sport in sports {
         if sport.name.as_str() == sport_name {
             return true;
         }
     }
-    return false;
+    false
 }
 
 /**
@@ -177,12 +185,12 @@ pub fn has_correct_gender(attendee:&IdentifiedAttendee, team_sport:&Sport) -> bo
 /**
  * Checks if the attendee has already registered in a team of the same sport
  */
-pub async fn has_team(db: &mut MySqlConnection, attendee:&IdentifiedAttendee, sport: &str) -> bool {
-    let row = sqlx::query("SELECT t.id, t.name
-    FROM teams t, team_members tm
-    WHERE tm.team_id = t.id
-    AND tm.attendee_id = ?
-    AND t.sport = ?").bind(attendee.id).bind(sport)
+pub async fn has_team(db: &mut MySqlConnection, event: &EventConfig, attendee:&IdentifiedAttendee, sport: &str) -> bool {
+    let row = sqlx::query("SELECT team_id
+    FROM team_members
+    WHERE attendee_id = ?
+    AND event_id = ?
+    AND sport = ?").bind(attendee.id).bind(event.event_id).bind(sport)
     .fetch_optional(db).await;
 
     match row {
@@ -200,10 +208,10 @@ pub async fn has_team(db: &mut MySqlConnection, attendee:&IdentifiedAttendee, sp
  * 
  * This applies the max_teams_per_school policy
  */
-pub async fn  can_school_register_team(db: &mut MySqlConnection, attendee:&IdentifiedAttendee, sport: &Sport) -> bool {
+pub async fn  can_school_register_team(db: &mut MySqlConnection, event: &EventConfig, school_id: u32, sport: &Sport) -> bool {
     let row = sqlx::query(
-        "SELECT COUNT(*) FROM teams t WHERE t.school_id = ? AND t.sport = ?")
-        .bind(attendee.school_id).bind(&sport.name).fetch_one(&mut *db).await;
+        "SELECT COUNT(*) FROM teams t WHERE t.event_id = ? AND t.school_id = ? AND t.sport = ? AND t.deleted_at IS NULL")
+        .bind(event.event_id).bind(school_id).bind(&sport.name).fetch_one(&mut *db).await;
 
     match row {
         Ok(r) => {
@@ -219,18 +227,18 @@ pub async fn  can_school_register_team(db: &mut MySqlConnection, attendee:&Ident
     }
 }
 
-pub async fn validate_attendee(db: &mut MySqlConnection, attendee:&IdentifiedAttendee, sport: &Sport) -> AttendeeStatus {
-    let athlete_tickets = config::get_option("athlete_ticket_ids");
-    let mut is_an_athlete = false;
-    for ticket_id in athlete_tickets.split(',') {
-        let id = ticket_id.parse::<u32>().unwrap();
-        if id == attendee.ticket_id {
-            is_an_athlete = true;
-        }
-    }
+/**
+ * Error given when a school already has max_teams_per_school teams in the sport
+ */
+pub fn school_quota_message(sport: &Sport) -> String {
+    format!("Your school has already registered {} teams in {}, it is not possible to register more teams", sport.max_teams_per_school, sport.name)
+}
+
+pub async fn validate_attendee(db: &mut MySqlConnection, event: &EventConfig, attendee:&IdentifiedAttendee, sport: &Sport) -> AttendeeStatus {
+    let is_an_athlete = event.is_athlete_ticket(attendee.ticket_id);
 
     // Check if attendee sports are valid
-    if attendee.sports.len() == 0 {
+    if attendee.sports.is_empty() {
         AttendeeStatus::InvalidSport
     }
     else if !is_an_athlete {
@@ -244,7 +252,7 @@ pub async fn validate_attendee(db: &mut MySqlConnection, attendee:&IdentifiedAtt
         AttendeeStatus::InvalidGender
     }
     // Check if attendee is already in a team
-    else if has_team(&mut *db, attendee, sport.name.as_str()).await {
+    else if has_team(&mut *db, event, attendee, sport.name.as_str()).await {
         AttendeeStatus::AlreadyInATeam
     }
     else {
@@ -252,42 +260,164 @@ pub async fn validate_attendee(db: &mut MySqlConnection, attendee:&IdentifiedAtt
     }
 }
 
-pub async fn validate_team(db: &mut MySqlConnection, team:&Team, sport: Sport) -> Result<Vec<IdentifiedAttendee>, String> {
+/**
+ * Human readable explanation of an attendee status
+ */
+pub fn status_message(status: AttendeeStatus, fullname: &str, sport: &Sport) -> String {
+    match status {
+        AttendeeStatus::SportNotRegistered =>
+            format!("{fullname} did not register in {}", sport.name),
+        AttendeeStatus::InvalidSport =>
+            format!("{fullname} has an invalid sport name or sport is unavailable"),
+        AttendeeStatus::AlreadyInATeam =>
+            format!("{fullname} is already in a {} team", sport.name),
+        AttendeeStatus::NotAnAthlete =>
+            format!("{fullname} is a supporter, not an athlete"),
+        AttendeeStatus::InvalidGender =>
+            format!("{} does not allow mixed teams, every team member must be of gender {:?}", sport.name, sport.gender),
+        AttendeeStatus::Ok =>
+            String::from("Ok")
+    }
+}
+
+/**
+ * First and last name of an attendee, example : 'John Doe'
+ */
+pub async fn attendee_name(db: &mut MySqlConnection, attendee_id: u32) -> Option<String> {
+    let row = sqlx::query("SELECT first_name, last_name FROM attendees WHERE id = ?")
+        .bind(attendee_id)
+        .fetch_optional(&mut *db)
+        .await
+        .ok()??;
+    Some(format!("{} {}", row.get::<String, usize>(0), row.get::<String, usize>(1)))
+}
+
+/**
+ * Validates every member of a team, the first reference being the captain
+ *
+ * Every invalid reference is reported, not only the first one
+ */
+pub async fn validate_team(db: &mut MySqlConnection, event: &EventConfig, team:&Team, sport: &Sport) -> Result<Vec<IdentifiedAttendee>, TeamValidationReport> {
+    let mut report = TeamValidationReport::default();
+
     // Check for duplicate references
+    let mut duplicates: Vec<&String> = vec![];
     for reference in &team.refs {
         let count = team.refs.iter().filter(|&r| *r == *reference).count();
-        if count > 1 {
-            return Err(String::from("The same order reference was found at least twice in payload"));
+        if count > 1 && !duplicates.contains(&reference) {
+            duplicates.push(reference);
+            report.add_team_problem(
+                TeamProblemKind::DuplicateReference,
+                format!("The order reference '{reference}' was found {count} times in the team")
+            );
         }
     }
 
     // Re-validate attendees
     let mut attendee_list:Vec<IdentifiedAttendee> = Vec::new();
+    let mut schools:Vec<(String, u32)> = Vec::new();
+    let mut seen:Vec<&String> = Vec::new();
     for reference in &team.refs {
-        let attendee = retrieve_attendee(&mut *db, reference.as_str()).await;
-        if attendee.is_err() {
-            return Err(format!("Order reference \'{reference}\' is invalid"));
+        // Duplicates are already reported, check them only once
+        if seen.contains(&reference) {
+            continue;
         }
-        else {
-            let id_attendee = attendee.unwrap();
-            let status = validate_attendee(&mut *db, &id_attendee, &sport).await;
-            if  status != AttendeeStatus::Ok {
-                let m = TeamMember::from_identified_attendee(&id_attendee, &mut *db).await;
-                return Err(format!("{} {} ({reference}) causes a validation error, code:{:?}", m.first_name, m.last_name, status));
-            }
-            else {
-                attendee_list.push(id_attendee);
+        seen.push(reference);
+        match retrieve_attendee(&mut *db, event, reference.as_str()).await {
+            Ok(id_attendee) => {
+                schools.push((reference.clone(), id_attendee.school_id));
+                let status = validate_attendee(&mut *db, event, &id_attendee, sport).await;
+                if status != AttendeeStatus::Ok {
+                    let name = attendee_name(&mut *db, id_attendee.id).await;
+                    let fullname = name.clone().unwrap_or_else(|| reference.clone());
+                    report.add_reference_problem(reference, name, Some(status), format!("{} ({reference})", status_message(status, &fullname, sport)));
+                }
+                else {
+                    attendee_list.push(id_attendee);
+                }
             }
+            Err(e) => report.add_reference_problem(reference, None, None, format!("Order reference '{reference}' is invalid : {e}"))
         }
     }
     //Check matching school ids
     if !sport.school_mix_allowed {
-        let captain = attendee_list.first().unwrap();
-        for member in &attendee_list {
-            if captain.school_id != member.school_id {
-                return Err(format!("Members of a team should all come from the same school"));
+        if let Some((_, captain_school_id)) = schools.first() {
+            let others: Vec<&str> = schools.iter()
+                .filter(|(_, school_id)| school_id != captain_school_id)
+                .map(|(reference, _)| reference.as_str())
+                .collect();
+            if !others.is_empty() {
+                report.add_team_problem(
+                    TeamProblemKind::SchoolMix,
+                    format!("Members of a {} team should all come from the captain's school, which is not the case of {}", sport.name, others.join(", "))
+                );
             }
         }
     }
-    Ok(attendee_list)
-}
\ No newline at end of file
+    match report.is_valid() {
+        true => Ok(attendee_list),
+        false => Err(report)
+    }
+}
+
+/**
+ * Runs every check a team has to pass before being registered
+ *
+ * Registration must be open and the sport must exist, then every problem
+ * with the size of the team, its members and its school is reported at once
+ */
+pub async fn check_team(db: &mut MySqlConnection, event: &EventConfig, team: &Team) -> Result<(Sport, Vec<IdentifiedAttendee>), TeamValidationReport> {
+    let mut report = TeamValidationReport::default();
+    if let Some(message) = event.registration_closed(&team.sport) {
+        report.add_team_problem(TeamProblemKind::RegistrationClosed, message);
+        return Err(report);
+    }
+
+    let attendee_gender = match team.gender {
+        SportGender::M => Some(AttendeeGender::M),
+        SportGender::F => Some(AttendeeGender::F),
+        SportGender::Mixed => None
+    };
+    let sport = match event.find_sport(&team.sport, attendee_gender) {
+        Ok(s) => s,
+        Err(_) => {
+            report.add_team_problem(TeamProblemKind::UnknownSport, String::from("Invalid 'sport' field in JSON payload"));
+            return Err(report);
+        }
+    };
+
+    // Check number of team members, open teams are completed later with join requests
+    let nb_members = team.refs.len();
+    let min_players = match team.open {
+        true => 1,
+        false => usize::from(sport.min_players)
+    };
+    if nb_members < min_players || nb_members > usize::from(sport.max_players) {
+        let message = match team.open {
+            true => format!("Invalid number of team members, an open team should have between 1 and {} and not {nb_members}", sport.max_players),
+            false => format!("Invalid number of team members, it should be between {} and {} and not {nb_members}", sport.min_players, sport.max_players)
+        };
+        report.add_team_problem(TeamProblemKind::InvalidSize, message);
+    }
+
+    let attendee_list = match validate_team(&mut *db, event, team, &sport).await {
+        Ok(al) => al,
+        Err(member_report) => {
+            report.team.extend(member_report.team);
+            report.references.extend(member_report.references);
+            vec![]
+        }
+    };
+
+    // The team belongs to the school of its captain, first of the list
+    if let Some(captain) = attendee_list.first() {
+        if !can_school_register_team(&mut *db, event, captain.school_id, &sport).await {
+            report.add_team_problem(TeamProblemKind::SchoolQuota, school_quota_message(&sport));
+        }
+    }
+
+    match report.is_valid() {
+        true => Ok((sport, attendee_list)),
+        false => Err(report)
+    }
+}
@@ -1,122 +1,1251 @@
+use std::collections::HashMap;
 use std::env;
-use ini::Ini;
+use std::fs;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use ini::{Ini, Properties};
+use rocket::request::{self, FromRequest, Request};
+use rocket::outcome::IntoOutcome;
+use rocket::http::Status;
+use rocket::tokio::sync::watch;
+use rocket::serde::Serialize;
+use rocket_db_pools::sqlx::types::chrono::{Local, NaiveDateTime};
 use crate::defs::*;
 
-pub fn get_option(opt_name: &str) -> String {
-    let filename = env::var("EAG_API_CONFIG");
-    let i: Ini;
-    if filename.is_err() {
-        i = Ini::load_from_file("sample.conf").unwrap();
+/**
+ * How often the configuration file is checked for modifications
+ */
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/**
+ * Age of an offline check-in snapshot after which its scans are refused, a day of gates
+ */
+const DEFAULT_SNAPSHOT_MAX_AGE_HOURS: u32 = 24;
+
+/**
+ * Format of the dates of the configuration file, in the server's local time
+ * example : 2023-05-01 23:59
+ */
+pub const DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/**
+ * Player bounds of a sport, as written in its configuration section
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub enum SportBounds {
+    Mixed { min: u8, max: u8 },
+    Strict { min_m: u8, max_m: u8, min_f: u8, max_f: u8 }
+}
+
+/**
+ * Composition rules of a sport, one per sport section of the configuration file
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct SportRules {
+    pub name: String,
+    pub bounds: SportBounds,
+    pub max_teams_per_school: u8,
+    pub school_mix_allowed: bool,
+    /**
+     * Teams of the sport can be registered from registration_opens until registration_closes,
+     * both optional
+     */
+    pub registration_opens: Option<NaiveDateTime>,
+    pub registration_closes: Option<NaiveDateTime>,
+    /**
+     * After this date only staff members can change the teams of the sport
+     */
+    pub roster_freeze: Option<NaiveDateTime>,
+    /**
+     * Number of athletes who can sign up to the sport, first come first served,
+     * None when the sport has no sign-up
+     */
+    pub capacity: Option<u32>,
+    pub signup_opens: Option<NaiveDateTime>,
+    pub signup_closes: Option<NaiveDateTime>
+}
+
+/**
+ * What the holder of a ticket receives at check-in, one per [entitlements:<slug>:<name>] section
+ *
+ * Colors are written as #rrggbb
+ */
+#[derive(Serialize, Clone, PartialEq, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct TicketEntitlements {
+    /**
+     * Name of the formula, example : All inclusive
+     */
+    pub name: String,
+    #[serde(skip)]
+    pub ticket_ids: Vec<u32>,
+    /**
+     * Colors of the formula wristbands, an all inclusive ticket gets two of them
+     */
+    pub wristbands: Vec<String>,
+    /**
+     * Color of the party wristband, None without party access
+     */
+    pub party_wristband: Option<String>,
+    /**
+     * Parties the ticket gives access to, example : Thursday, Saturday
+     */
+    pub parties: Vec<String>,
+    pub breakfasts: u8,
+    pub meals: u8,
+    pub dinners: u8
+}
+
+/**
+ * Food ticket spent at a meal service
+ */
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum MealKind {
+    Breakfast,
+    Meal,
+    Dinner
+}
+
+impl MealKind {
+    /**
+     * Name of the kind, as written in the configuration file and in the database
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            MealKind::Breakfast => "breakfast",
+            MealKind::Meal => "meal",
+            MealKind::Dinner => "dinner"
+        }
     }
-    else {
-        i = Ini::load_from_file(filename.unwrap()).unwrap();
+}
+
+impl FromStr for MealKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<MealKind, ()> {
+        match s {
+            "breakfast" => Ok(MealKind::Breakfast),
+            "meal" => Ok(MealKind::Meal),
+            "dinner" => Ok(MealKind::Dinner),
+            _ => Err(())
+        }
     }
-    for(sec, prop) in i.iter() {
-        if sec.unwrap() == "main" {
-            let opt = prop.get(opt_name);
-            if opt.is_some() {
-                let mut s = String::new();
-                s.push_str(opt.unwrap());
-                return s;
+}
+
+/**
+ * A meal served by the food stands, one per [meals:<slug>:<name>] section
+ *
+ * Each attendee can be served once per service, with a food ticket of its kind
+ */
+#[derive(Serialize, Clone, PartialEq, Debug)]
+#[serde(crate = "rocket::serde")]
+pub struct MealService {
+    /**
+     * Name of the service, example : Saturday dinner
+     */
+    pub name: String,
+    pub kind: MealKind,
+    /**
+     * Food stands serve it from opens until closes, both optional
+     */
+    #[serde(skip)]
+    pub opens: Option<NaiveDateTime>,
+    #[serde(skip)]
+    pub closes: Option<NaiveDateTime>
+}
+
+/**
+ * Options of one competition, found under its [event:<slug>] section
+ *
+ * Its sports are the [<slug>:<sport name>] sections, the entitlements of
+ * its tickets the [entitlements:<slug>:<name>] sections and its meal
+ * services the [meals:<slug>:<name>] sections
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct EventConfig {
+    /**
+     * Identifies the event in URLs, example : /e/<slug>/welcome/<order_ref>
+     */
+    pub slug: String,
+    pub name: String,
+    /**
+     * Attendize event and account of this competition
+     */
+    pub event_id: u32,
+    pub account_id: u32,
+    pub athlete_ticket_ids: Vec<u32>,
+    pub male_sport_question_ids: Vec<u32>,
+    pub female_sport_question_ids: Vec<u32>,
+    pub gender_question_id: u32,
+    pub school_question_id: u32,
+    pub phone_question_id: u32,
+    pub check_in_read_only: bool,
+    /**
+     * Offline check-in snapshots older than this are refused at sync
+     */
+    pub snapshot_max_age_hours: u32,
+    pub team_registration_open: bool,
+    pub sports: HashMap<String, SportRules>,
+    pub entitlements: Vec<TicketEntitlements>,
+    /**
+     * In the order of the configuration file
+     */
+    pub meal_services: Vec<MealService>
+}
+
+/**
+ * Whole configuration file, parsed and validated once at launch
+ */
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    pub events: HashMap<String, EventConfig>
+}
+
+/**
+ * Path of the configuration file, taken from EAG_API_CONFIG (defaults to sample.conf)
+ */
+pub fn config_path() -> String {
+    env::var("EAG_API_CONFIG").unwrap_or_else(|_| String::from("sample.conf"))
+}
+
+/**
+ * Collects the errors of every option read from a section,
+ * so that all of them can be reported at once
+ */
+struct SectionReader<'a> {
+    section: &'a str,
+    prop: &'a Properties,
+    errors: &'a mut Vec<String>
+}
+
+impl<'a> SectionReader<'a> {
+    fn raw(&mut self, key: &str) -> Option<&'a str> {
+        let value = self.prop.get(key);
+        if value.is_none() {
+            self.errors.push(format!("Missing option '{key}' under [{}]", self.section));
+        }
+        value
+    }
+
+    fn parse<T: FromStr>(&mut self, key: &str, expected: &str) -> Option<T> {
+        let value = self.raw(key)?;
+        self.parse_value(key, value, expected)
+    }
+
+    fn parse_or<T: FromStr>(&mut self, key: &str, expected: &str, default: T) -> T {
+        match self.prop.get(key) {
+            Some(value) => self.parse_value(key, value, expected).unwrap_or(default),
+            None => default
+        }
+    }
+
+    /**
+     * Reads an optional option, None when it is missing or invalid
+     */
+    fn parse_or_none<T: FromStr>(&mut self, key: &str, expected: &str) -> Option<T> {
+        let value = self.prop.get(key)?;
+        self.parse_value(key, value, expected)
+    }
+
+    /**
+     * Reads an optional date, example : 2023-05-01 23:59
+     */
+    fn date_or_none(&mut self, key: &str) -> Option<NaiveDateTime> {
+        let value = self.prop.get(key)?;
+        match NaiveDateTime::parse_from_str(value.trim(), DATE_FORMAT) {
+            Ok(date) => Some(date),
+            Err(_) => {
+                self.errors.push(format!("Invalid value '{value}' for '{key}' under [{}], expected a date such as 2023-05-01 23:59", self.section));
+                None
+            }
+        }
+    }
+
+    fn parse_value<T: FromStr>(&mut self, key: &str, value: &str, expected: &str) -> Option<T> {
+        match value.trim().parse::<T>() {
+            Ok(v) => Some(v),
+            Err(_) => {
+                self.errors.push(format!("Invalid value '{value}' for '{key}' under [{}], expected {expected}", self.section));
+                None
+            }
+        }
+    }
+
+    /**
+     * Reads an optional comma separated list, empty when the option is missing
+     * example : Thursday, Saturday
+     */
+    fn list_or_empty(&mut self, key: &str) -> Vec<String> {
+        match self.prop.get(key) {
+            Some(value) => value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(String::from).collect(),
+            None => vec![]
+        }
+    }
+
+    /**
+     * Checks that every value is a color such as #ff0c0c
+     */
+    fn check_colors(&mut self, key: &str, colors: &[String]) {
+        for color in colors {
+            let is_color = color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+            if !is_color {
+                self.errors.push(format!("Invalid color '{color}' for '{key}' under [{}], expected a color such as #ff0c0c", self.section));
             }
-            else {
-                panic!("Missing option in configuration file under section [main]: {}", opt_name);
+        }
+    }
+
+    /**
+     * Reports the options of the section that are not in the list, such as a misspelled name
+     */
+    fn check_unknown(&mut self, known: &[&str]) {
+        for (key, _) in self.prop.iter() {
+            if !known.contains(&key) {
+                self.errors.push(format!("Unknown option '{key}' under [{}]", self.section));
             }
         }
     }
-    panic!("Missing section [main] in configuration file");
+
+    /**
+     * Reads a comma separated list of ids, optionally wrapped in parenthesis
+     * example : (5, 6, 8)
+     */
+    fn id_list(&mut self, key: &str) -> Option<Vec<u32>> {
+        let value = self.raw(key)?;
+        let mut ids = vec![];
+        for id in value.trim().trim_start_matches('(').trim_end_matches(')').split(',') {
+            ids.push(self.parse_value(key, id, "a comma separated list of ids")?);
+        }
+        Some(ids)
+    }
 }
 
-pub fn find_sport(sport: &str, gender:Option<AttendeeGender>) -> Result<Sport, String> {
-    let filename = env::var("EAG_API_CONFIG");
-    let i: Ini;
-    if filename.is_err() {
-        i = Ini::load_from_file("sample.conf").unwrap();
+impl EventConfig {
+    fn from_section(slug: &str, section_name: &str, prop: &Properties, errors: &mut Vec<String>) -> Option<EventConfig> {
+        let mut r = SectionReader { section: section_name, prop, errors };
+        r.check_unknown(&[
+            "name", "event_id", "account_id", "athlete_ticket_ids", "male_sport_question_ids",
+            "female_sport_question_ids", "gender_question_id", "school_question_id", "phone_question_id",
+            "check_in_read_only", "snapshot_max_age_hours", "team_registration_open"
+        ]);
+        let name = r.raw("name");
+        let event_id = r.parse("event_id", "an event id");
+        let account_id = r.parse("account_id", "an account id");
+        let athlete_ticket_ids = r.id_list("athlete_ticket_ids");
+        let male_sport_question_ids = r.id_list("male_sport_question_ids");
+        let female_sport_question_ids = r.id_list("female_sport_question_ids");
+        let gender_question_id = r.parse("gender_question_id", "a question id");
+        let school_question_id = r.parse("school_question_id", "a question id");
+        let phone_question_id = r.parse("phone_question_id", "a question id");
+        let check_in_read_only = r.parse("check_in_read_only", "'true' or 'false'");
+        let snapshot_max_age_hours = r.parse_or("snapshot_max_age_hours", "a number of hours", DEFAULT_SNAPSHOT_MAX_AGE_HOURS);
+        let team_registration_open = r.parse("team_registration_open", "'true' or 'false'");
+
+        Some(EventConfig {
+            slug: String::from(slug),
+            name: String::from(name?),
+            event_id: event_id?,
+            account_id: account_id?,
+            athlete_ticket_ids: athlete_ticket_ids?,
+            male_sport_question_ids: male_sport_question_ids?,
+            female_sport_question_ids: female_sport_question_ids?,
+            gender_question_id: gender_question_id?,
+            school_question_id: school_question_id?,
+            phone_question_id: phone_question_id?,
+            check_in_read_only: check_in_read_only?,
+            snapshot_max_age_hours,
+            team_registration_open: team_registration_open?,
+            sports: HashMap::new(),
+            entitlements: vec![],
+            meal_services: vec![]
+        })
     }
-    else {
-        i = Ini::load_from_file(filename.unwrap()).unwrap();
+
+    /**
+     * Entitlements of a ticket, None when no section lists it
+     */
+    pub fn ticket_entitlements(&self, ticket_id: u32) -> Option<&TicketEntitlements> {
+        self.entitlements.iter().find(|e| e.ticket_ids.contains(&ticket_id))
     }
-    for (sec, prop) in i.iter() {
-        let section_name = sec.unwrap();
-        if section_name == sport {
-            // Check if sport type is strict or mixed
-            let sport_type = prop.get("gender");
-            if sport_type.is_some() {
-                let max_teams_per_school:u8;
-                let mut school_mix_allowed:bool = false;
 
-                match prop.get("max_teams_per_school") {
-                    Some(o) => max_teams_per_school = o.parse().unwrap(),
-                    None => return Err(format!("Missing max_teams_per_school in [{section_name}]"))
-                }
+    pub fn meal_service(&self, name: &str) -> Option<&MealService> {
+        self.meal_services.iter().find(|s| s.name == name)
+    }
 
-                match prop.get("school_mix_allowed") {
-                    Some(o) => school_mix_allowed = o.parse().unwrap(),
-                    None => ()
-                }
+    /**
+     * Why food stands cannot serve this meal right now, None when they can
+     */
+    pub fn meal_service_closed(&self, name: &str) -> Option<String> {
+        let service = match self.meal_service(name) {
+            Some(service) => service,
+            None => return Some(format!("Unknown meal service '{name}'"))
+        };
+        let now = now();
+        match (service.opens, service.closes) {
+            (Some(opens), _) if now < opens =>
+                Some(format!("{name} is served from {}", opens.format(DATE_FORMAT))),
+            (_, Some(closes)) if now >= closes =>
+                Some(format!("{name} is over since {}", closes.format(DATE_FORMAT))),
+            _ => None
+        }
+    }
 
-                // Does the sport support mixed teams or strict teams ?
-                match sport_type.unwrap() {
-                    //This sport supports mixed teams
-                    "mixed" => {
-                        let min_opt = prop.get("min");
-                        let max_opt = prop.get("max");
-                        if min_opt.is_some() && max_opt.is_some() { //if min and max are both defined
-                            // convert min and max to i8 and return a structure
-                            let s = Sport {
-                                name: String::from(section_name),
-                                min_players: min_opt.unwrap().parse::<u8>().unwrap(),
-                                max_players: max_opt.unwrap().parse::<u8>().unwrap(),
-                                gender: SportGender::Mixed,
-                                max_teams_per_school: max_teams_per_school,
-                                school_mix_allowed: school_mix_allowed
-                            };
-                            return Ok(s);
-                        }
-                        else {
-                            return Err(format!("Missing fields under [{section_name}], it sould include fields \'min\' and \'max\'"));
+    /**
+     * Why a team of this sport cannot be registered right now, None when it can
+     */
+    pub fn registration_closed(&self, sport: &str) -> Option<String> {
+        if !self.team_registration_open {
+            return Some(String::from("It is currently not possible to register a team"));
+        }
+        let rules = self.sports.get(sport)?;
+        let now = now();
+        match (rules.registration_opens, rules.registration_closes) {
+            (Some(opens), _) if now < opens =>
+                Some(format!("Registration of {sport} teams opens on {}", opens.format(DATE_FORMAT))),
+            (_, Some(closes)) if now >= closes =>
+                Some(format!("Registration of {sport} teams closed on {}", closes.format(DATE_FORMAT))),
+            _ => None
+        }
+    }
+
+    /**
+     * Why captains cannot change a team of this sport anymore, None until its roster freeze
+     */
+    pub fn roster_frozen(&self, sport: &str) -> Option<String> {
+        let freeze = self.sports.get(sport)?.roster_freeze?;
+        match now() >= freeze {
+            true => Some(format!(
+                "Teams of {sport} are frozen since {}, contact the sport team to change them",
+                freeze.format(DATE_FORMAT)
+            )),
+            false => None
+        }
+    }
+
+    /**
+     * Date until which captains can complete the teams of the sport : its roster
+     * freeze, or the end of its registration when it has no freeze
+     */
+    pub fn team_deadline(&self, sport: &str) -> Option<NaiveDateTime> {
+        let rules = self.sports.get(sport)?;
+        rules.roster_freeze.or(rules.registration_closes)
+    }
+
+    /**
+     * Whether the team deadline of the sport is still ahead, and at most this many days away
+     */
+    pub fn deadline_within(&self, sport: &str, days: i64) -> bool {
+        let now = now();
+        self.team_deadline(sport).is_some_and(|deadline| deadline > now && (deadline - now).num_seconds() <= days.saturating_mul(24 * 3600))
+    }
+
+    /**
+     * Why athletes cannot sign up to this sport right now, None when they can
+     */
+    pub fn signup_closed(&self, sport: &str) -> Option<String> {
+        let rules = match self.sports.get(sport) {
+            Some(rules) if rules.capacity.is_some() => rules,
+            _ => return Some(format!("There is no sign-up for {sport}"))
+        };
+        let now = now();
+        match (rules.signup_opens, rules.signup_closes) {
+            (Some(opens), _) if now < opens =>
+                Some(format!("Sign-ups for {sport} open on {}", opens.format(DATE_FORMAT))),
+            (_, Some(closes)) if now >= closes =>
+                Some(format!("Sign-ups for {sport} closed on {}", closes.format(DATE_FORMAT))),
+            _ => None
+        }
+    }
+
+    /**
+     * Sports with a capacity, in alphabetical order
+     */
+    pub fn signup_sports(&self) -> Vec<&SportRules> {
+        let mut sports: Vec<&SportRules> = self.sports.values().filter(|s| s.capacity.is_some()).collect();
+        sports.sort_by(|a, b| a.name.cmp(&b.name));
+        sports
+    }
+
+    pub fn find_sport(&self, sport: &str, gender: Option<AttendeeGender>) -> Result<Sport, String> {
+        match self.sports.get(sport) {
+            Some(rules) => rules.for_gender(gender),
+            None => Err(format!("Unknown sport '{sport}'"))
+        }
+    }
+
+    /**
+     * Lists what changed between this event and its newer version
+     * example : [games:Football] max_teams_per_school 4 -> 5
+     */
+    fn diff(&self, new: &EventConfig, changes: &mut Vec<String>) {
+        let slug = &self.slug;
+        let old_options = EventConfig { sports: HashMap::new(), entitlements: vec![], meal_services: vec![], ..self.clone() };
+        let new_options = EventConfig { sports: HashMap::new(), entitlements: vec![], meal_services: vec![], ..new.clone() };
+        if old_options != new_options {
+            changes.push(format!("[event:{slug}] options changed"));
+        }
+        for old_entitlements in &self.entitlements {
+            let name = &old_entitlements.name;
+            match new.entitlements.iter().find(|e| e.name == *name) {
+                Some(new_entitlements) if new_entitlements != old_entitlements => changes.push(format!("[entitlements:{slug}:{name}] changed")),
+                Some(_) => (),
+                None => changes.push(format!("[entitlements:{slug}:{name}] removed"))
+            }
+        }
+        for new_entitlements in &new.entitlements {
+            if !self.entitlements.iter().any(|e| e.name == new_entitlements.name) {
+                changes.push(format!("[entitlements:{slug}:{}] added", new_entitlements.name));
+            }
+        }
+        for old_service in &self.meal_services {
+            let name = &old_service.name;
+            match new.meal_service(name) {
+                Some(new_service) if new_service != old_service => changes.push(format!("[meals:{slug}:{name}] changed")),
+                Some(_) => (),
+                None => changes.push(format!("[meals:{slug}:{name}] removed"))
+            }
+        }
+        for new_service in &new.meal_services {
+            if self.meal_service(&new_service.name).is_none() {
+                changes.push(format!("[meals:{slug}:{}] added", new_service.name));
+            }
+        }
+        let mut names: Vec<&String> = self.sports.keys().chain(new.sports.keys()).collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            match (self.sports.get(name), new.sports.get(name)) {
+                (Some(_), None) => changes.push(format!("[{slug}:{name}] removed")),
+                (None, Some(_)) => changes.push(format!("[{slug}:{name}] added")),
+                (Some(old_rules), Some(new_rules)) => {
+                    let old_fields = old_rules.fields();
+                    for (key, value) in new_rules.fields() {
+                        match old_fields.iter().find(|(k, _)| *k == key) {
+                            Some((_, old_value)) if *old_value == value => (),
+                            Some((_, old_value)) => changes.push(format!("[{slug}:{name}] {key} {old_value} -> {value}")),
+                            None => changes.push(format!("[{slug}:{name}] {key} -> {value}"))
                         }
                     }
-                    //This sport supports only one gender per team
-                    "strict" => {
-                        let min_m_opt = prop.get("minM");
-                        let max_m_opt = prop.get("maxM");
-                        let min_f_opt = prop.get("minF");
-                        let max_f_opt = prop.get("maxF");
-                        if min_m_opt.is_none() || max_m_opt.is_none() || min_f_opt.is_none() || max_f_opt.is_none() {
-                            return Err(format!("Missing fields under [{section_name}], it sould include fields \'minM\', \'maxM\', \'maxF\' and \'maxF\'"))
-                        }
-                        if gender.is_some() {
-                            match gender.unwrap() {
-                                AttendeeGender::M => {
-                                    return Ok(Sport { name: String::from(section_name),
-                                                        min_players: min_m_opt.unwrap().parse::<u8>().unwrap(), 
-                                                        max_players: max_m_opt.unwrap().parse::<u8>().unwrap(), 
-                                                        gender: SportGender::M,
-                                                        max_teams_per_school: max_teams_per_school,
-                                                        school_mix_allowed: school_mix_allowed});
-                                },
-                                AttendeeGender::F => {
-                                    return Ok(Sport { name: String::from(section_name), 
-                                        min_players: min_f_opt.unwrap().parse::<u8>().unwrap(), 
-                                        max_players: max_f_opt.unwrap().parse::<u8>().unwrap(), 
-                                        gender: SportGender::F,
-                                        max_teams_per_school: max_teams_per_school,
-                                        school_mix_allowed: school_mix_allowed });
+                }
+                (None, None) => ()
+            }
+        }
+    }
+
+    pub fn is_athlete_ticket(&self, ticket_id: u32) -> bool {
+        self.athlete_ticket_ids.contains(&ticket_id)
+    }
+
+    /**
+     * Sport question ids for an attendee's gender, formatted for an SQL IN clause
+     */
+    pub fn sport_question_ids(&self, gender: AttendeeGender) -> String {
+        let ids = match gender {
+            AttendeeGender::M => &self.male_sport_question_ids,
+            AttendeeGender::F => &self.female_sport_question_ids
+        };
+        sql_id_list(ids)
+    }
+
+    /**
+     * Sport question ids of both genders, formatted for an SQL IN clause
+     */
+    pub fn all_sport_question_ids(&self) -> String {
+        let mut ids: Vec<u32> = self.male_sport_question_ids.iter()
+            .chain(self.female_sport_question_ids.iter())
+            .copied()
+            .collect();
+        ids.sort();
+        ids.dedup();
+        sql_id_list(&ids)
+    }
+}
+
+impl TicketEntitlements {
+    fn from_section(name: &str, section_name: &str, prop: &Properties, errors: &mut Vec<String>) -> Option<TicketEntitlements> {
+        let mut r = SectionReader { section: section_name, prop, errors };
+        r.check_unknown(&["ticket_ids", "wristbands", "party_wristband", "parties", "breakfasts", "meals", "dinners"]);
+        let ticket_ids = r.id_list("ticket_ids");
+        let wristbands = r.list_or_empty("wristbands");
+        r.check_colors("wristbands", &wristbands);
+        let party_wristband: Option<String> = r.prop.get("party_wristband").map(|c| String::from(c.trim()));
+        r.check_colors("party_wristband", party_wristband.as_slice());
+        let parties = r.list_or_empty("parties");
+        if party_wristband.is_none() && !parties.is_empty() {
+            r.errors.push(format!("parties are listed without a party_wristband under [{section_name}]"));
+        }
+        let breakfasts = r.parse_or("breakfasts", "a number", 0);
+        let meals = r.parse_or("meals", "a number", 0);
+        let dinners = r.parse_or("dinners", "a number", 0);
+
+        Some(TicketEntitlements {
+            name: String::from(name),
+            ticket_ids: ticket_ids?,
+            wristbands,
+            party_wristband,
+            parties,
+            breakfasts,
+            meals,
+            dinners
+        })
+    }
+}
+
+impl MealService {
+    fn from_section(name: &str, section_name: &str, prop: &Properties, errors: &mut Vec<String>) -> Option<MealService> {
+        let mut r = SectionReader { section: section_name, prop, errors };
+        r.check_unknown(&["kind", "opens", "closes"]);
+        let kind = r.parse("kind", "'breakfast', 'meal' or 'dinner'");
+        let opens = r.date_or_none("opens");
+        let closes = r.date_or_none("closes");
+        if let (Some(opens), Some(closes)) = (opens, closes) {
+            if opens >= closes {
+                r.errors.push(format!("opens must be before closes under [{section_name}]"));
+            }
+        }
+
+        Some(MealService {
+            name: String::from(name),
+            kind: kind?,
+            opens,
+            closes
+        })
+    }
+}
+
+impl SportRules {
+    fn from_section(name: &str, section_name: &str, prop: &Properties, errors: &mut Vec<String>) -> Option<SportRules> {
+        let mut r = SectionReader { section: section_name, prop, errors };
+        r.check_unknown(&[
+            "gender", "min", "max", "minM", "maxM", "minF", "maxF", "max_teams_per_school", "school_mix_allowed",
+            "registration_opens", "registration_closes", "roster_freeze", "capacity", "signup_opens", "signup_closes"
+        ]);
+        let max_teams_per_school = r.parse("max_teams_per_school", "a number");
+        let school_mix_allowed = r.parse_or("school_mix_allowed", "'true' or 'false'", false);
+        let registration_opens = r.date_or_none("registration_opens");
+        let registration_closes = r.date_or_none("registration_closes");
+        let roster_freeze = r.date_or_none("roster_freeze");
+        let capacity = r.parse_or_none("capacity", "a number of athletes");
+        let signup_opens = r.date_or_none("signup_opens");
+        let signup_closes = r.date_or_none("signup_closes");
+        if let (Some(opens), Some(closes)) = (registration_opens, registration_closes) {
+            if opens >= closes {
+                r.errors.push(format!("registration_opens should be before registration_closes under [{section_name}]"));
+            }
+        }
+        if let (Some(opens), Some(closes)) = (signup_opens, signup_closes) {
+            if opens >= closes {
+                r.errors.push(format!("signup_opens should be before signup_closes under [{section_name}]"));
+            }
+        }
+        // Teams are frozen once they could all be registered
+        if let (Some(freeze), Some(closes)) = (roster_freeze, registration_closes) {
+            if freeze < closes {
+                r.errors.push(format!("roster_freeze should not be before registration_closes under [{section_name}]"));
+            }
+        }
+        if let (Some(freeze), Some(opens)) = (roster_freeze, registration_opens) {
+            if freeze <= opens {
+                r.errors.push(format!("roster_freeze should be after registration_opens under [{section_name}]"));
+            }
+        }
+
+        // Does the sport support mixed teams or strict teams ?
+        let bounds = match r.raw("gender")? {
+            //This sport supports mixed teams
+            "mixed" => {
+                let min = r.parse("min", "a number");
+                let max = r.parse("max", "a number");
+                SportBounds::Mixed { min: min?, max: max? }
+            }
+            //This sport supports only one gender per team
+            "strict" => {
+                let min_m = r.parse("minM", "a number");
+                let max_m = r.parse("maxM", "a number");
+                let min_f = r.parse("minF", "a number");
+                let max_f = r.parse("maxF", "a number");
+                SportBounds::Strict { min_m: min_m?, max_m: max_m?, min_f: min_f?, max_f: max_f? }
+            }
+            // When type option is not valid
+            other => {
+                r.errors.push(format!("Invalid sport type under [{section_name}], is has to be either 'mixed' or 'strict' : '{other}' is invalid"));
+                return None;
+            }
+        };
+
+        let inverted = match bounds {
+            SportBounds::Mixed { min, max } => min > max,
+            SportBounds::Strict { min_m, max_m, min_f, max_f } => min_m > max_m || min_f > max_f
+        };
+        if inverted {
+            r.errors.push(format!("Minimum number of players is greater than the maximum under [{section_name}]"));
+            return None;
+        }
+
+        Some(SportRules {
+            name: String::from(name),
+            bounds,
+            max_teams_per_school: max_teams_per_school?,
+            school_mix_allowed,
+            registration_opens,
+            registration_closes,
+            roster_freeze,
+            capacity,
+            signup_opens,
+            signup_closes
+        })
+    }
+
+    /**
+     * Builds the Sport a team of the given gender has to comply with
+     */
+    pub fn for_gender(&self, gender: Option<AttendeeGender>) -> Result<Sport, String> {
+        let (min_players, max_players, sport_gender) = match (&self.bounds, gender) {
+            (SportBounds::Mixed { min, max }, _) => (*min, *max, SportGender::Mixed),
+            (SportBounds::Strict { min_m, max_m, .. }, Some(AttendeeGender::M)) => (*min_m, *max_m, SportGender::M),
+            (SportBounds::Strict { min_f, max_f, .. }, Some(AttendeeGender::F)) => (*min_f, *max_f, SportGender::F),
+            (SportBounds::Strict { .. }, None) =>
+                return Err(format!("Sport {} is strict and attendee gender is required", self.name))
+        };
+        Ok(Sport {
+            name: self.name.clone(),
+            min_players,
+            max_players,
+            gender: sport_gender,
+            max_teams_per_school: self.max_teams_per_school,
+            school_mix_allowed: self.school_mix_allowed
+        })
+    }
+
+    /**
+     * Options of the sport section as (key, value) pairs, used to log what changed on reload
+     */
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![];
+        match self.bounds {
+            SportBounds::Mixed { min, max } => {
+                fields.push(("gender", String::from("mixed")));
+                fields.push(("min", min.to_string()));
+                fields.push(("max", max.to_string()));
+            }
+            SportBounds::Strict { min_m, max_m, min_f, max_f } => {
+                fields.push(("gender", String::from("strict")));
+                fields.push(("minM", min_m.to_string()));
+                fields.push(("maxM", max_m.to_string()));
+                fields.push(("minF", min_f.to_string()));
+                fields.push(("maxF", max_f.to_string()));
+            }
+        }
+        fields.push(("max_teams_per_school", self.max_teams_per_school.to_string()));
+        fields.push(("school_mix_allowed", self.school_mix_allowed.to_string()));
+        fields.push(("capacity", self.capacity.map_or_else(|| String::from("none"), |c| c.to_string())));
+        let dates = [
+            ("registration_opens", self.registration_opens),
+            ("registration_closes", self.registration_closes),
+            ("roster_freeze", self.roster_freeze),
+            ("signup_opens", self.signup_opens),
+            ("signup_closes", self.signup_closes)
+        ];
+        for (key, date) in dates {
+            let value = date.map_or_else(|| String::from("none"), |d| d.format(DATE_FORMAT).to_string());
+            fields.push((key, value));
+        }
+        fields
+    }
+}
+
+impl AppConfig {
+    /**
+     * Loads the configuration file pointed by EAG_API_CONFIG
+     *
+     * Returns every error found in the file, not only the first one
+     */
+    pub fn load() -> Result<AppConfig, Vec<String>> {
+        let filename = config_path();
+        match Ini::load_from_file(&filename) {
+            Ok(i) => AppConfig::from_ini(&i),
+            Err(e) => Err(vec![format!("Unable to read configuration file '{filename}' : {e}")])
+        }
+    }
+
+    pub fn from_ini(i: &Ini) -> Result<AppConfig, Vec<String>> {
+        let mut errors: Vec<String> = vec![];
+        let mut events: HashMap<String, EventConfig> = HashMap::new();
+
+        // Events first, sports are attached to them afterwards
+        for (sec, prop) in i.iter() {
+            // Options written before the first section are ignored
+            let section_name = match sec {
+                Some(name) => name,
+                None => continue
+            };
+            if let Some(slug) = section_name.strip_prefix("event:") {
+                if let Some(event) = EventConfig::from_section(slug, section_name, prop, &mut errors) {
+                    events.insert(String::from(slug), event);
+                }
+            }
+        }
+        for (sec, prop) in i.iter() {
+            let section_name = match sec {
+                Some(name) if name.starts_with("entitlements:") => name,
+                _ => continue
+            };
+            match section_name["entitlements:".len()..].split_once(':') {
+                Some((slug, name)) => {
+                    let entitlements = TicketEntitlements::from_section(name, section_name, prop, &mut errors);
+                    match (events.get_mut(slug), entitlements) {
+                        (Some(event), Some(entitlements)) => {
+                            // A ticket gets the entitlements of a single section
+                            for ticket_id in &entitlements.ticket_ids {
+                                if let Some(other) = event.ticket_entitlements(*ticket_id) {
+                                    errors.push(format!("Ticket {ticket_id} is listed under [{section_name}] and [entitlements:{slug}:{}]", other.name));
                                 }
-                            }   
+                            }
+                            event.entitlements.push(entitlements);
                         }
-                        else {
-                            return Err(format!("Sport {section_name} is strict and attendee gender is required"));
+                        (None, _) => errors.push(format!("Section [{section_name}] refers to an unknown event, missing section [event:{slug}]")),
+                        (Some(_), None) => ()
+                    }
+                }
+                None => errors.push(format!("Unknown section [{section_name}], entitlements are written as [entitlements:<event slug>:<name>]"))
+            }
+        }
+        for (sec, prop) in i.iter() {
+            let section_name = match sec {
+                Some(name) if name.starts_with("meals:") => name,
+                _ => continue
+            };
+            match section_name["meals:".len()..].split_once(':') {
+                Some((slug, name)) => {
+                    let service = MealService::from_section(name, section_name, prop, &mut errors);
+                    match (events.get_mut(slug), service) {
+                        (Some(event), Some(service)) => event.meal_services.push(service),
+                        (None, _) => errors.push(format!("Section [{section_name}] refers to an unknown event, missing section [event:{slug}]")),
+                        (Some(_), None) => ()
+                    }
+                }
+                None => errors.push(format!("Unknown section [{section_name}], meal services are written as [meals:<event slug>:<name>]"))
+            }
+        }
+        for (sec, prop) in i.iter() {
+            let section_name = match sec {
+                Some(name) if !name.starts_with("event:") && !name.starts_with("entitlements:") && !name.starts_with("meals:") => name,
+                _ => continue
+            };
+            match section_name.split_once(':') {
+                Some((slug, sport_name)) => {
+                    let rules = SportRules::from_section(sport_name, section_name, prop, &mut errors);
+                    match (events.get_mut(slug), rules) {
+                        (Some(event), Some(rules)) => {
+                            event.sports.insert(String::from(sport_name), rules);
                         }
+                        (None, _) => errors.push(format!("Section [{section_name}] refers to an unknown event, missing section [event:{slug}]")),
+                        (Some(_), None) => ()
                     }
-                    // When type option is not valid 
-                    _other => {
-                        return Err(format!("Invalid sport type under [{section_name}], is has to be either \'mixed\' or \'strict\' : \'{_other}\' is invalid"))
-                    } 
                 }
+                None => errors.push(format!("Unknown section [{section_name}], sports are written as [<event slug>:<sport name>]"))
+            }
+        }
+        if events.is_empty() {
+            errors.push(String::from("No event in configuration file, add at least one [event:<slug>] section"));
+        }
+
+        match errors.is_empty() {
+            true => Ok(AppConfig { events }),
+            false => Err(errors)
+        }
+    }
+
+    pub fn event(&self, slug: &str) -> Option<&EventConfig> {
+        self.events.get(slug)
+    }
+
+    /**
+     * Event with this Attendize id, for addresses sent before events had a slug
+     */
+    pub fn event_by_id(&self, event_id: u32) -> Option<&EventConfig> {
+        self.events.values().find(|e| e.event_id == event_id)
+    }
+
+    /**
+     * Lists what changed between this configuration and a newer one
+     * example : [games:Football] max_teams_per_school 4 -> 5
+     */
+    pub fn diff(&self, new: &AppConfig) -> Vec<String> {
+        let mut changes: Vec<String> = vec![];
+        let mut slugs: Vec<&String> = self.events.keys().chain(new.events.keys()).collect();
+        slugs.sort();
+        slugs.dedup();
+        for slug in slugs {
+            match (self.events.get(slug), new.events.get(slug)) {
+                (Some(_), None) => changes.push(format!("[event:{slug}] removed")),
+                (None, Some(_)) => changes.push(format!("[event:{slug}] added")),
+                (Some(old_event), Some(new_event)) => old_event.diff(new_event, &mut changes),
+                (None, None) => ()
             }
         }
+        changes
     }
-    return Err(String::from("Unknown error"));
-}
\ No newline at end of file
+}
+
+/**
+ * Current time of the server, dates of the configuration file are local times
+ */
+fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+/**
+ * Formats ids for an SQL IN clause
+ * example : [5, 6, 8] -> (5,6,8)
+ */
+pub fn sql_id_list(ids: &[u32]) -> String {
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    format!("({})", ids.join(","))
+}
+
+/**
+ * Configuration shared by every request, replaced as a whole when the file is reloaded
+ *
+ * A reload only takes effect if the new file is entirely valid,
+ * otherwise the running configuration is kept
+ */
+#[derive(Clone)]
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<AppConfig>>>,
+    reloading: Arc<Mutex<()>>,
+    reloaded: Arc<watch::Sender<Arc<AppConfig>>>
+}
+
+impl SharedConfig {
+    pub fn new(config: AppConfig) -> SharedConfig {
+        let config = Arc::new(config);
+        SharedConfig {
+            current: Arc::new(RwLock::new(config.clone())),
+            reloading: Arc::new(Mutex::new(())),
+            reloaded: Arc::new(watch::channel(config).0)
+        }
+    }
+
+    /**
+     * Receives every configuration swapped in by a reload
+     */
+    pub fn subscribe(&self) -> watch::Receiver<Arc<AppConfig>> {
+        self.reloaded.subscribe()
+    }
+
+    pub fn get(&self) -> Arc<AppConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /**
+     * Re-reads the configuration file and swaps it in if it is valid
+     *
+     * Returns the list of changes, or every error found in the file
+     */
+    pub fn reload(&self) -> Result<Vec<String>, Vec<String>> {
+        let _reloading = self.reloading.lock().unwrap();
+        match AppConfig::load() {
+            Ok(new) => {
+                let changes = self.get().diff(&new);
+                let new = Arc::new(new);
+                *self.current.write().unwrap() = new.clone();
+                self.reloaded.send_replace(new);
+                if changes.is_empty() {
+                    info!("Configuration reloaded, nothing changed");
+                }
+                for change in &changes {
+                    info!("Configuration reloaded : {change}");
+                }
+                Ok(changes)
+            }
+            Err(errors) => {
+                for e in &errors {
+                    error!("Configuration not reloaded : {e}");
+                }
+                Err(errors)
+            }
+        }
+    }
+}
+
+fn modified_time() -> Option<SystemTime> {
+    fs::metadata(config_path()).ok()?.modified().ok()
+}
+
+/**
+ * Reloads the configuration every time the file is modified
+ */
+pub async fn watch(config: SharedConfig) {
+    let mut last_modified = modified_time();
+    loop {
+        rocket::tokio::time::sleep(WATCH_INTERVAL).await;
+        let modified = modified_time();
+        if modified.is_some() && modified != last_modified {
+            last_modified = modified;
+            // Errors are already logged, the running configuration is kept
+            let _ = config.reload();
+        }
+    }
+}
+
+/**
+ * Request guard giving the configuration as it was when the request arrived
+ */
+pub struct LiveConfig(Arc<AppConfig>);
+
+impl Deref for LiveConfig {
+    type Target = AppConfig;
+
+    fn deref(&self) -> &AppConfig {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LiveConfig {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<LiveConfig, ()> {
+        req.rocket().state::<SharedConfig>()
+            .map(|config| LiveConfig(config.get()))
+            .into_outcome((Status::InternalServerError, ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENT: &str = "
+[event:games]
+name = Games
+event_id = 2
+account_id = 1
+athlete_ticket_ids = 1,2,3
+male_sport_question_ids = (5, 6)
+female_sport_question_ids = (5, 7)
+gender_question_id = 17
+school_question_id = 15
+phone_question_id = 4
+check_in_read_only = false
+team_registration_open = true
+";
+
+    fn load(sections: &str) -> Result<AppConfig, Vec<String>> {
+        let ini = Ini::load_from_str(&format!("{EVENT}{sections}")).unwrap();
+        AppConfig::from_ini(&ini)
+    }
+
+    #[test]
+    fn entitlements_are_found_by_ticket_id() {
+        let config = load("
+[entitlements:games:All Access]
+ticket_ids = (1, 2)
+wristbands = #e2ff28, #60c1ff
+party_wristband = #60c1ff
+parties = Opening party, Closing party
+breakfasts = 3
+meals = 2
+dinners = 3
+
+[entitlements:games:Sport only]
+ticket_ids = 3
+wristbands = #ff0c0c
+meals = 2
+").unwrap();
+        let event = config.event("games").unwrap();
+
+        let all_access = event.ticket_entitlements(2).unwrap();
+        assert_eq!(all_access.name, "All Access");
+        assert_eq!(all_access.wristbands, vec!["#e2ff28", "#60c1ff"]);
+        assert_eq!(all_access.party_wristband.as_deref(), Some("#60c1ff"));
+        assert_eq!(all_access.parties, vec!["Opening party", "Closing party"]);
+        assert_eq!((all_access.breakfasts, all_access.meals, all_access.dinners), (3, 2, 3));
+        assert_eq!(event.ticket_entitlements(1), Some(all_access));
+
+        let sport_only = event.ticket_entitlements(3).unwrap();
+        assert_eq!(sport_only.name, "Sport only");
+        assert_eq!(sport_only.party_wristband, None);
+        assert!(sport_only.parties.is_empty());
+        assert_eq!((sport_only.breakfasts, sport_only.meals, sport_only.dinners), (0, 2, 0));
+    }
+
+    #[test]
+    fn ticket_without_entitlements() {
+        let config = load("
+[entitlements:games:Sport only]
+ticket_ids = 3
+meals = 2
+").unwrap();
+        assert_eq!(config.event("games").unwrap().ticket_entitlements(1), None);
+    }
+
+    #[test]
+    fn unknown_option_is_reported() {
+        let errors = load("
+[entitlements:games:All Access]
+ticket_ids = 1
+diners = 3
+").unwrap_err();
+        assert_eq!(errors, vec!["Unknown option 'diners' under [entitlements:games:All Access]"]);
+    }
+
+    #[test]
+    fn invalid_counts_are_reported() {
+        let errors = load("
+[entitlements:games:All Access]
+ticket_ids = 1
+meals = two
+dinners = -1
+").unwrap_err();
+        assert_eq!(errors, vec![
+            "Invalid value 'two' for 'meals' under [entitlements:games:All Access], expected a number",
+            "Invalid value '-1' for 'dinners' under [entitlements:games:All Access], expected a number"
+        ]);
+    }
+
+    #[test]
+    fn ticket_in_two_sections_is_reported() {
+        let errors = load("
+[entitlements:games:All Access]
+ticket_ids = 1, 2
+
+[entitlements:games:Sport only]
+ticket_ids = 2
+").unwrap_err();
+        assert_eq!(errors, vec!["Ticket 2 is listed under [entitlements:games:Sport only] and [entitlements:games:All Access]"]);
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let ini = Ini::load_from_str("
+[event:games]
+name = Games
+event_id = two
+account_id = 1
+athlete_ticket_ids = 1,2,3
+male_sport_question_ids = (5, 6)
+female_sport_question_ids = (5, x)
+school_question_id = 15
+phone_question_id = 4
+check_in_read_only = false
+team_registration_open = true
+
+[games:Football]
+gender = mixed
+max_teams_per_school = 4
+min = 12
+max = 8
+").unwrap();
+        let errors = AppConfig::from_ini(&ini).unwrap_err();
+        assert_eq!(errors, vec![
+            "Invalid value 'two' for 'event_id' under [event:games], expected an event id",
+            "Invalid value ' x' for 'female_sport_question_ids' under [event:games], expected a comma separated list of ids",
+            "Missing option 'gender_question_id' under [event:games]",
+            "Minimum number of players is greater than the maximum under [games:Football]",
+            "Section [games:Football] refers to an unknown event, missing section [event:games]",
+            "No event in configuration file, add at least one [event:<slug>] section"
+        ]);
+    }
+
+    #[test]
+    fn unknown_options_are_reported_in_every_section() {
+        let errors = load("
+snapshot_max_age = 12
+
+[games:Tennis]
+gender = mixed
+max_teams_per_school = 5
+min = 2
+max = 2
+registration_close = 2023-05-01 23:59
+
+[meals:games:Saturday dinner]
+kind = dinner
+open = 2023-05-06 19:00
+").unwrap_err();
+        assert_eq!(errors, vec![
+            "Unknown option 'snapshot_max_age' under [event:games]",
+            "Unknown option 'open' under [meals:games:Saturday dinner]",
+            "Unknown option 'registration_close' under [games:Tennis]"
+        ]);
+    }
+
+    #[test]
+    fn dates_out_of_order_are_reported() {
+        let errors = load("
+[games:Cross Country]
+gender = mixed
+max_teams_per_school = 1
+min = 1
+max = 1
+registration_opens = 2023-04-01 00:00
+registration_closes = 2023-05-01 23:59
+roster_freeze = 2023-04-15 23:59
+capacity = 150
+signup_opens = 2023-05-01 00:00
+signup_closes = 2023-04-01 00:00
+").unwrap_err();
+        assert_eq!(errors, vec![
+            "signup_opens should be before signup_closes under [games:Cross Country]",
+            "roster_freeze should not be before registration_closes under [games:Cross Country]"
+        ]);
+    }
+
+    #[test]
+    fn ordered_dates_are_accepted() {
+        let config = load("
+[games:Cross Country]
+gender = mixed
+max_teams_per_school = 1
+min = 1
+max = 1
+registration_opens = 2023-04-01 00:00
+registration_closes = 2023-05-01 23:59
+roster_freeze = 2023-05-03 23:59
+capacity = 150
+signup_opens = 2023-04-01 00:00
+signup_closes = 2023-05-01 00:00
+").unwrap();
+        let rules = &config.event("games").unwrap().sports["Cross Country"];
+        assert_eq!(rules.capacity, Some(150));
+        assert!(rules.roster_freeze > rules.registration_closes);
+    }
+
+    const TENNIS: &str = "
+[games:Tennis]
+gender = mixed
+max_teams_per_school = 5
+min = 2
+max = 2
+";
+
+    #[test]
+    fn unchanged_config_has_no_diff() {
+        let old = load(TENNIS).unwrap();
+        let new = load(TENNIS).unwrap();
+        assert!(old.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_events_are_listed() {
+        let old = load(TENNIS).unwrap();
+        let new = load(&format!("{TENNIS}{}", EVENT.replace("[event:games]", "[event:cup]"))).unwrap();
+        assert_eq!(old.diff(&new), vec!["[event:cup] added"]);
+        assert_eq!(new.diff(&old), vec!["[event:cup] removed"]);
+    }
+
+    #[test]
+    fn changed_sport_is_listed() {
+        let old = load(TENNIS).unwrap();
+        let new = load(&format!("{}\nroster_freeze = 2023-05-01 23:59\n", TENNIS.replace("max_teams_per_school = 5", "max_teams_per_school = 6"))).unwrap();
+        assert_eq!(old.diff(&new), vec![
+            "[games:Tennis] max_teams_per_school 5 -> 6",
+            "[games:Tennis] roster_freeze none -> 2023-05-01 23:59"
+        ]);
+        assert_eq!(new.diff(&load("").unwrap()), vec!["[games:Tennis] removed"]);
+    }
+}
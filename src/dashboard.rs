@@ -0,0 +1,215 @@
+//! Arrival statistics of the check-in dashboard, refreshed in the browsers
+//! every time a ticket is checked in
+
+use rocket::serde::Serialize;
+use rocket::tokio::sync::broadcast;
+
+use rocket_db_pools::sqlx;
+use rocket_db_pools::sqlx::Row;
+use rocket_db_pools::sqlx::mysql::MySqlConnection;
+
+use crate::config::EventConfig;
+
+/**
+ * Arrivals not yet sent to a slow browser before it is told to reload everything
+ */
+const FEED_CAPACITY: usize = 256;
+
+/**
+ * A check-in, or its undo, as announced to the dashboards
+ */
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct Arrival {
+    /**
+     * Slug of the event, dashboards only receive the arrivals of their event
+     */
+    #[serde(skip)]
+    pub event: String,
+    pub name: String,
+    /**
+     * False when the check-in was undone
+     */
+    pub arrived: bool
+}
+
+/**
+ * Channel from the check-in routes to the dashboards, managed by Rocket
+ */
+#[derive(Clone)]
+pub struct ArrivalFeed(broadcast::Sender<Arrival>);
+
+impl Default for ArrivalFeed {
+    fn default() -> ArrivalFeed {
+        ArrivalFeed(broadcast::channel(FEED_CAPACITY).0)
+    }
+}
+
+impl ArrivalFeed {
+    pub fn announce(&self, event: &EventConfig, name: String, arrived: bool) {
+        // Nobody is listening when no dashboard is open
+        let _ = self.0.send(Arrival { event: event.slug.clone(), name, arrived });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arrival> {
+        self.0.subscribe()
+    }
+}
+
+/**
+ * Attendees arrived and expected in one group, cancelled tickets are not expected
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ArrivalCount {
+    pub label: String,
+    pub arrived: i64,
+    pub expected: i64
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HourCount {
+    /**
+     * Start of the hour, example : 2023-05-12 14:00
+     */
+    pub hour: String,
+    pub arrived: i64
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MissingAttendee {
+    pub name: String,
+    pub attendee_ref: String,
+    pub ticket_title: String
+}
+
+/**
+ * Attendees of a school who have not arrived yet
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MissingDelegation {
+    pub school: String,
+    pub attendees: Vec<MissingAttendee>
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ArrivalStats {
+    pub arrived: i64,
+    pub expected: i64,
+    pub by_school: Vec<ArrivalCount>,
+    pub by_ticket: Vec<ArrivalCount>,
+    /**
+     * An athlete counts in each of their sports
+     */
+    pub by_sport: Vec<ArrivalCount>,
+    pub by_hour: Vec<HourCount>,
+    pub missing: Vec<MissingDelegation>
+}
+
+fn counts(rows: Vec<sqlx::mysql::MySqlRow>) -> Vec<ArrivalCount> {
+    rows.iter()
+        .map(|row| ArrivalCount {
+            label: row.get(0),
+            arrived: row.get(1),
+            expected: row.get(2)
+        })
+        .collect()
+}
+
+/**
+ * Arrivals of the event against the attendees expected, by school, ticket, sport and hour
+ */
+pub async fn arrival_stats(db: &mut MySqlConnection, event: &EventConfig) -> Result<ArrivalStats, sqlx::Error> {
+    let by_school = counts(sqlx::query(
+        "SELECT COALESCE(qs.answer_text, 'Unknown school') school, COUNT(CASE WHEN a.has_arrived = 1 THEN 1 END), COUNT(*)
+        FROM attendees a
+        LEFT JOIN question_answers qs ON qs.attendee_id = a.id AND qs.question_id = ?
+        WHERE a.event_id = ? AND a.is_cancelled = 0
+        GROUP BY school ORDER BY school"
+    )
+    .bind(event.school_question_id)
+    .bind(event.event_id)
+    .fetch_all(&mut *db)
+    .await?);
+
+    let by_ticket = counts(sqlx::query(
+        "SELECT t.title, COUNT(CASE WHEN a.has_arrived = 1 THEN 1 END), COUNT(*)
+        FROM attendees a
+        JOIN tickets t ON a.ticket_id = t.id
+        WHERE a.event_id = ? AND a.is_cancelled = 0
+        GROUP BY t.id, t.title ORDER BY t.title"
+    )
+    .bind(event.event_id)
+    .fetch_all(&mut *db)
+    .await?);
+
+    let sports_stmt = format!(
+        "SELECT qp.answer_text, COUNT(DISTINCT CASE WHEN a.has_arrived = 1 THEN a.id END), COUNT(DISTINCT a.id)
+        FROM attendees a
+        JOIN question_answers qp ON qp.attendee_id = a.id AND qp.question_id IN {}
+        WHERE a.event_id = ? AND a.is_cancelled = 0
+        GROUP BY qp.answer_text ORDER BY qp.answer_text",
+        event.all_sport_question_ids()
+    );
+    let by_sport = counts(sqlx::query(&sports_stmt)
+        .bind(event.event_id)
+        .fetch_all(&mut *db)
+        .await?);
+
+    let by_hour = sqlx::query(
+        "SELECT DATE_FORMAT(a.arrival_time, '%Y-%m-%d %H:00') hour, COUNT(*)
+        FROM attendees a
+        WHERE a.event_id = ? AND a.is_cancelled = 0 AND a.has_arrived = 1 AND a.arrival_time IS NOT NULL
+        GROUP BY hour ORDER BY hour"
+    )
+    .bind(event.event_id)
+    .fetch_all(&mut *db)
+    .await?
+    .iter()
+    .map(|row| HourCount {
+        hour: row.get(0),
+        arrived: row.get(1)
+    })
+    .collect();
+
+    let mut missing: Vec<MissingDelegation> = vec![];
+    for row in sqlx::query(
+        "SELECT COALESCE(qs.answer_text, 'Unknown school') school, CONCAT(a.first_name, ' ', a.last_name),
+            CONCAT(o.order_reference, '-', a.reference_index), t.title
+        FROM attendees a
+        JOIN orders o ON a.order_id = o.id
+        JOIN tickets t ON a.ticket_id = t.id
+        LEFT JOIN question_answers qs ON qs.attendee_id = a.id AND qs.question_id = ?
+        WHERE a.event_id = ? AND a.is_cancelled = 0 AND a.has_arrived = 0
+        ORDER BY school, a.last_name, a.first_name"
+    )
+    .bind(event.school_question_id)
+    .bind(event.event_id)
+    .fetch_all(&mut *db)
+    .await? {
+        let school: String = row.get(0);
+        let attendee = MissingAttendee {
+            name: row.get(1),
+            attendee_ref: row.get(2),
+            ticket_title: row.get(3)
+        };
+        match missing.last_mut() {
+            Some(delegation) if delegation.school == school => delegation.attendees.push(attendee),
+            _ => missing.push(MissingDelegation { school, attendees: vec![attendee] })
+        }
+    }
+
+    Ok(ArrivalStats {
+        arrived: by_school.iter().map(|c| c.arrived).sum(),
+        expected: by_school.iter().map(|c| c.expected).sum(),
+        by_school,
+        by_ticket,
+        by_sport,
+        by_hour,
+        missing
+    })
+}
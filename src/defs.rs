@@ -4,11 +4,13 @@ use rocket_db_pools::sqlx::Row;
 use rocket_db_pools::{sqlx};
 use rocket_db_pools::sqlx::mysql::MySqlConnection;
 
+use crate::config::{EventConfig, TicketEntitlements};
+
 /**
  * ------ Type Definitions
  */
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
 #[serde(crate = "rocket::serde")]
 pub enum SportGender {
     M,
@@ -16,6 +18,28 @@ pub enum SportGender {
     Mixed
 }
 
+impl SportGender {
+    /**
+     * Gender of a team as stored in the teams table
+     */
+    pub fn stored_name(&self) -> &'static str {
+        match self {
+            SportGender::M => "Male",
+            SportGender::F => "Female",
+            SportGender::Mixed => "Mixed"
+        }
+    }
+
+    pub fn from_stored_name(name: &str) -> Option<SportGender> {
+        match name {
+            "Male" => Some(SportGender::M),
+            "Female" => Some(SportGender::F),
+            "Mixed" => Some(SportGender::Mixed),
+            _ => None
+        }
+    }
+}
+
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
 #[serde(crate = "rocket::serde")]
@@ -79,14 +103,13 @@ impl TeamMember {
                     sports.push(String::from(&sport.name));
                 }
 
-                let tm:TeamMember = TeamMember { 
+                TeamMember { 
                     attendee_id: attendee.id,
                     first_name: String::from(row.get::<&str, usize>(0)), 
                     last_name: String::from(row.get::<&str, usize>(1)),
                     school: school_name_fut.await.unwrap().get::<String, usize>(0),
-                    sports: sports 
-                };
-                return tm;
+                    sports
+                }
             }
             Err(_) => panic!("Unable to convert IdentifiedAttendee to TeamMember")
         }
@@ -108,7 +131,7 @@ pub struct CompleteTeamMember {
 }
 
 impl CompleteTeamMember {
-    pub async fn from_team_member(db: &mut MySqlConnection, member: &TeamMember) -> CompleteTeamMember {
+    pub async fn from_team_member(db: &mut MySqlConnection, event: &EventConfig, member: &TeamMember) -> CompleteTeamMember {
         let result = sqlx::query(
             "SELECT a.email, qa.answer_text, qb.answer_text,
             CONCAT(o.order_reference, '-', a.reference_index) attendee_ref
@@ -116,8 +139,10 @@ impl CompleteTeamMember {
             JOIN orders o ON a.order_id = o.id
             JOIN question_answers qa ON qa.attendee_id = a.id
             JOIN question_answers qb ON qb.attendee_id = a.id
-            WHERE qa.question_id = 3 AND qb.question_id = 17 AND a.id = ?"
+            WHERE qa.question_id = ? AND qb.question_id = ? AND a.id = ?"
         )
+        .bind(event.phone_question_id)
+        .bind(event.gender_question_id)
         .bind(member.attendee_id)
         .fetch_one(&mut *db).await;
         let email:String;
@@ -132,25 +157,25 @@ impl CompleteTeamMember {
                 gender = r.get(2);
             }
             Err(_) => {
-                email = format!("none");
-                phone = format!("none");
-                attendee_ref = format!("none");
-                gender = format!("none");
+                email = String::from("none");
+                phone = String::from("none");
+                attendee_ref = String::from("none");
+                gender = String::from("none");
             }
         }
         CompleteTeamMember {
             attendee_id: member.attendee_id,
             first_name: member.first_name.clone(),
             last_name: member.last_name.clone(),
-            gender: gender,
+            gender,
             school: member.school.clone(),
             sports: member.sports.clone(),
-            email: email,
-            phone: phone,
-            attendee_ref: attendee_ref
+            email,
+            phone,
+            attendee_ref
         }
     }
-    pub async fn from_attendee_id(db: &mut MySqlConnection, attendee_id:u32) -> Option<CompleteTeamMember> {
+    pub async fn from_attendee_id(db: &mut MySqlConnection, event: &EventConfig, attendee_id:u32) -> Option<CompleteTeamMember> {
         let r = sqlx::query(
             "SELECT a.first_name, a.last_name, a.email, qa.answer_text phone, qb.answer_text gender, qc.answer_text school,
             CONCAT(o.order_reference, '-', a.reference_index) attendee_ref
@@ -159,16 +184,19 @@ impl CompleteTeamMember {
             JOIN question_answers qa ON qa.attendee_id = a.id
             JOIN question_answers qb ON qb.attendee_id = a.id
             JOIN question_answers qc ON qc.attendee_id = a.id
-            WHERE qa.question_id = 4 AND qb.question_id = 17 AND qc.question_id = 15
+            WHERE qa.question_id = ? AND qb.question_id = ? AND qc.question_id = ?
             AND a.id = ?"
         )
+        .bind(event.phone_question_id)
+        .bind(event.gender_question_id)
+        .bind(event.school_question_id)
         .bind(attendee_id)
         .fetch_one(&mut *db)
         .await
         .ok()?;
 
         let mut member = CompleteTeamMember {
-            attendee_id: attendee_id,
+            attendee_id,
             first_name: r.get(0),
             last_name: r.get(1),
             gender: r.get(4),
@@ -179,9 +207,11 @@ impl CompleteTeamMember {
             attendee_ref: r.get(6)
         };
 
-        let sports = sqlx::query(
-            "SELECT DISTINCT(answer_text) FROM question_answers WHERE attendee_id = ? AND question_id IN (5, 6, 7, 8)"
-        )
+        let sports_stmt = format!(
+            "SELECT DISTINCT(answer_text) FROM question_answers WHERE attendee_id = ? AND question_id IN {}",
+            event.all_sport_question_ids()
+        );
+        let sports = sqlx::query(&sports_stmt)
         .bind(member.attendee_id)
         .fetch_all(&mut *db)
         .await
@@ -193,6 +223,14 @@ impl CompleteTeamMember {
 
         Some(member)
     }
+
+    /**
+     * The member as shown to people who cannot edit the team, without their email,
+     * phone and ticket reference
+     */
+    pub fn without_contact(self) -> CompleteTeamMember {
+        CompleteTeamMember { email: String::new(), phone: String::new(), attendee_ref: String::new(), ..self }
+    }
 }
 
 
@@ -200,11 +238,109 @@ impl CompleteTeamMember {
 #[serde(crate = "rocket::serde")]
 pub struct Team {
     pub name: String,
-    pub school_id: u32,
     pub sport: String,
     pub refs: Vec<String>,
-    pub gender: SportGender
+    pub gender: SportGender,
+    /**
+     * The team may be created with fewer than min_players members,
+     * the others join it with its join code
+     */
+    #[serde(default)]
+    pub open: bool
+}
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TeamRename {
+    pub name: String
+}
+
+/**
+ * Why a check-in is undone, example : scanned the ticket of a friend
+ */
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CheckInUndo {
+    pub reason: String
+}
+
+/**
+ * Proof that the athlete giving up their sign-up holds the ticket,
+ * the order reference in the address is printed on public pages
+ */
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SignupWithdrawal {
+    /**
+     * private_reference_number of the QR code of their ticket
+     */
+    pub ticket_number: String
+}
+
+/**
+ * Credentials a captain gives to edit their team
+ */
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CaptainLogin {
+    /**
+     * private_reference_number of the QR code of their ticket, which is never shown on team pages
+     */
+    pub ticket_number: String
+}
+
+/**
+ * Where a team is in its lifecycle, computed from its roster and the dates of its sport
+ */
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "rocket::serde")]
+pub enum TeamStatus {
+    /**
+     * Reserved by its captain, who is still alone in it
+     */
+    Draft,
+    /**
+     * Below the minimum number of players of its sport, even once frozen
+     */
+    Incomplete,
+    Valid,
+    /**
+     * Complete and past the roster freeze of its sport
+     */
+    Locked,
+    /**
+     * Deleted, until the sport team restores it
+     */
+    Withdrawn,
+    /**
+     * Its sport was removed from the configuration, there are no rules to check it against
+     */
+    UnknownSport
 }
+
+impl TeamStatus {
+    /**
+     * Status from its name in a filter, case insensitive
+     */
+    pub fn from_name(name: &str) -> Option<TeamStatus> {
+        match name.to_lowercase().as_str() {
+            "draft" => Some(TeamStatus::Draft),
+            "incomplete" => Some(TeamStatus::Incomplete),
+            "valid" => Some(TeamStatus::Valid),
+            "locked" => Some(TeamStatus::Locked),
+            "withdrawn" => Some(TeamStatus::Withdrawn),
+            "unknownsport" => Some(TeamStatus::UnknownSport),
+            _ => None
+        }
+    }
+
+    /**
+     * Draft and incomplete teams are not official yet
+     */
+    pub fn is_below_min(&self) -> bool {
+        matches!(self, TeamStatus::Draft | TeamStatus::Incomplete)
+    }
+}
+
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct TeamView {
@@ -212,10 +348,69 @@ pub struct TeamView {
     pub school: String,
     pub sport: String,
     pub gender: String,
-    pub uuid: String
+    pub uuid: String,
+    pub status: TeamStatus,
+    pub nb_members: i64,
+    pub min_players: u8,
+    /**
+     * Team deadline of the sport, example : 2023-05-01 23:59
+     */
+    pub deadline: Option<String>
 }
 
-#[derive(PartialEq, Debug)]
+/**
+ * A dissolved team, kept until the sport team restores it
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DeletedTeamView {
+    pub name: String,
+    pub school: String,
+    pub sport: String,
+    pub gender: String,
+    pub uuid: String,
+    pub deleted_at: String,
+    pub deleted_by: String,
+    pub nb_members: i64
+}
+
+/**
+ * A team as seen by the attendees who open its join link
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct JoinableTeamView {
+    pub name: String,
+    pub school: String,
+    pub sport: String,
+    pub gender: String,
+    pub captain: String,
+    pub nb_members: i64,
+    pub min_players: u8,
+    pub max_players: u8
+}
+
+/**
+ * A request to join a team, waiting for the answer of its captain
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct JoinRequestView {
+    pub id: u32,
+    pub name: String,
+    pub attendee_ref: String,
+    pub created_at: String
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct School {
+    pub id: u32,
+    pub name: String
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "rocket::serde")]
 pub enum AttendeeStatus {
     Ok,
     InvalidSport,
@@ -225,6 +420,85 @@ pub enum AttendeeStatus {
     NotAnAthlete
 }
 
+/**
+ * Problem found with one of the order references of a team
+ */
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct ReferenceProblem {
+    pub reference: String,
+    /**
+     * Unknown when the reference does not match any attendee
+     */
+    pub attendee_name: Option<String>,
+    pub status: Option<AttendeeStatus>,
+    pub message: String
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "rocket::serde")]
+pub enum TeamProblemKind {
+    RegistrationClosed,
+    UnknownSport,
+    InvalidSize,
+    DuplicateReference,
+    SchoolMix,
+    SchoolQuota,
+    CaptainRemoval,
+    InvalidName,
+    /**
+     * The roster freeze of the sport has passed, only staff members can edit the team
+     */
+    RosterFrozen
+}
+
+/**
+ * Problem found with the team as a whole
+ */
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct TeamProblem {
+    pub kind: TeamProblemKind,
+    pub message: String
+}
+
+/**
+ * Every problem found while validating a team, so that they can all be fixed at once
+ */
+#[derive(Serialize, Clone, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct TeamValidationReport {
+    pub team: Vec<TeamProblem>,
+    pub references: Vec<ReferenceProblem>
+}
+
+impl TeamValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.team.is_empty() && self.references.is_empty()
+    }
+
+    pub fn add_team_problem(&mut self, kind: TeamProblemKind, message: String) {
+        self.team.push(TeamProblem { kind, message });
+    }
+
+    pub fn add_reference_problem(&mut self, reference: &str, attendee_name: Option<String>, status: Option<AttendeeStatus>, message: String) {
+        self.references.push(ReferenceProblem {
+            reference: String::from(reference),
+            attendee_name,
+            status,
+            message
+        });
+    }
+
+    pub fn summary(&self) -> String {
+        let nb_problems = self.team.len() + self.references.len();
+        match nb_problems {
+            1 => String::from("1 problem was found with this team"),
+            n => format!("{n} problems were found with this team")
+        }
+    }
+}
+
 /**
  * ------- Reponse definitions
  */
@@ -244,10 +518,51 @@ pub struct SimpleResponse {
     pub code: SimpleResponseCode
 }
 
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct TeamResponse {
+    pub message: String,
+    pub code: SimpleResponseCode,
+    pub report: Option<TeamValidationReport>,
+    /**
+     * Uuid of the team that was just created
+     */
+    pub uuid: Option<String>
+}
+
 #[derive(Serialize, Clone)]
 #[serde(crate = "rocket::serde")]
 pub struct CheckAttendeeResponse {
     pub message: String,
     pub member: Option<CompleteTeamMember>,
-    pub ticket_title: String
-}
\ No newline at end of file
+    pub ticket_title: String,
+    /**
+     * What to hand out at check-in, None when the ticket is in no entitlements section
+     */
+    pub entitlements: Option<TicketEntitlements>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visitor_gets_no_contact_data() {
+        let member = CompleteTeamMember {
+            attendee_id: 12,
+            first_name: String::from("Ada"),
+            last_name: String::from("Lovelace"),
+            gender: String::from("F"),
+            school: String::from("ISAE"),
+            sports: vec![String::from("Volleyball")],
+            email: String::from("ada@example.com"),
+            phone: String::from("+33612345678"),
+            attendee_ref: String::from("ABC123-1")
+        };
+        let shown = rocket::serde::json::to_string(&member.without_contact()).unwrap();
+        assert!(shown.contains("Lovelace"));
+        for contact in ["ada@example.com", "+33612345678", "ABC123-1"] {
+            assert!(!shown.contains(contact), "{contact} is shown to a visitor");
+        }
+    }
+}
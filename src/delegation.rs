@@ -0,0 +1,204 @@
+//! Everything a delegation leader needs to follow the registrations of their school
+
+use std::collections::HashMap;
+
+use rocket::serde::Serialize;
+
+use rocket_db_pools::sqlx;
+use rocket_db_pools::sqlx::Row;
+use rocket_db_pools::sqlx::mysql::MySqlConnection;
+
+use crate::config::{EventConfig, SportBounds};
+
+/**
+ * Ids of the attendees of a school, to be used in an IN clause
+ * binds : school question id, school id
+ */
+const SCHOOL_ATTENDEES: &str = "SELECT qs.attendee_id FROM question_answers qs
+    JOIN question_options qo ON qo.question_id = qs.question_id AND qo.name = qs.answer_text
+    WHERE qs.question_id = ? AND qo.id = ?";
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DelegationAthlete {
+    pub attendee_id: u32,
+    pub first_name: String,
+    pub last_name: String,
+    pub attendee_ref: String,
+    pub sports: Vec<String>,
+    /**
+     * Teams the athlete is in, as 'sport : team name'
+     */
+    pub teams: Vec<String>
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DelegationTeam {
+    pub name: String,
+    pub sport: String,
+    pub gender: String,
+    pub uuid: String,
+    pub captain: String,
+    pub nb_members: i64
+}
+
+/**
+ * How many more teams the school may register in a sport
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SportSlots {
+    pub sport: String,
+    /**
+     * Strict sports have separate male and female teams
+     */
+    pub strict: bool,
+    pub max_teams: u8,
+    pub registered: i64,
+    pub remaining: i64
+}
+
+/**
+ * Athletes registered in a sport who are in none of its teams yet
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SportWithoutTeam {
+    pub sport: String,
+    pub athletes: Vec<String>
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct DelegationOverview {
+    pub school: String,
+    pub athletes: Vec<DelegationAthlete>,
+    pub teams: Vec<DelegationTeam>,
+    pub slots: Vec<SportSlots>,
+    pub without_team: Vec<SportWithoutTeam>
+}
+
+impl DelegationOverview {
+    pub async fn load(db: &mut MySqlConnection, event: &EventConfig, school_id: u32) -> Result<DelegationOverview, sqlx::Error> {
+        let school: String = sqlx::query("SELECT name FROM question_options WHERE id = ?")
+            .bind(school_id)
+            .fetch_one(&mut *db)
+            .await?
+            .get(0);
+
+        let athletes_stmt = format!(
+            "SELECT a.id, a.first_name, a.last_name, CONCAT(o.order_reference, '-', a.reference_index), a.ticket_id
+            FROM attendees a
+            JOIN orders o ON a.order_id = o.id
+            WHERE a.event_id = ? AND a.is_cancelled = 0 AND a.id IN ({SCHOOL_ATTENDEES})
+            ORDER BY a.last_name, a.first_name"
+        );
+        let athlete_rows = sqlx::query(&athletes_stmt)
+            .bind(event.event_id)
+            .bind(event.school_question_id)
+            .bind(school_id)
+            .fetch_all(&mut *db)
+            .await?;
+
+        let sports_stmt = format!(
+            "SELECT DISTINCT attendee_id, answer_text FROM question_answers
+            WHERE question_id IN {} AND attendee_id IN ({SCHOOL_ATTENDEES})",
+            event.all_sport_question_ids()
+        );
+        let mut sports: HashMap<u32, Vec<String>> = HashMap::new();
+        for row in sqlx::query(&sports_stmt)
+            .bind(event.school_question_id)
+            .bind(school_id)
+            .fetch_all(&mut *db)
+            .await? {
+            sports.entry(row.get(0)).or_default().push(row.get(1));
+        }
+
+        // Members of the school may be in teams of other schools when mixing is allowed
+        let memberships_stmt = format!(
+            "SELECT tm.attendee_id, t.sport, t.name FROM team_members tm
+            JOIN teams t ON tm.team_id = t.id
+            WHERE t.event_id = ? AND tm.attendee_id IN ({SCHOOL_ATTENDEES})"
+        );
+        let mut memberships: HashMap<u32, Vec<(String, String)>> = HashMap::new();
+        for row in sqlx::query(&memberships_stmt)
+            .bind(event.event_id)
+            .bind(event.school_question_id)
+            .bind(school_id)
+            .fetch_all(&mut *db)
+            .await? {
+            memberships.entry(row.get(0)).or_default().push((row.get(1), row.get(2)));
+        }
+
+        let mut athletes: Vec<DelegationAthlete> = vec![];
+        for row in athlete_rows {
+            if !event.is_athlete_ticket(row.get(4)) {
+                continue;
+            }
+            let attendee_id: u32 = row.get(0);
+            athletes.push(DelegationAthlete {
+                attendee_id,
+                first_name: row.get(1),
+                last_name: row.get(2),
+                attendee_ref: row.get(3),
+                sports: sports.remove(&attendee_id).unwrap_or_default(),
+                teams: memberships.get(&attendee_id)
+                    .map(|teams| teams.iter().map(|(sport, name)| format!("{sport} : {name}")).collect())
+                    .unwrap_or_default()
+            });
+        }
+
+        let teams: Vec<DelegationTeam> = sqlx::query(
+            "SELECT t.name, t.sport, t.gender, t.uuid, CONCAT(a.first_name, ' ', a.last_name), COUNT(tm.id)
+            FROM teams t
+            JOIN attendees a ON t.captain_id = a.id
+            LEFT JOIN team_members tm ON tm.team_id = t.id
+            WHERE t.event_id = ? AND t.school_id = ? AND t.deleted_at IS NULL
+            GROUP BY t.id, t.name, t.sport, t.gender, t.uuid, a.first_name, a.last_name
+            ORDER BY t.sport, t.name"
+        )
+        .bind(event.event_id)
+        .bind(school_id)
+        .fetch_all(&mut *db)
+        .await?
+        .iter()
+        .map(|row| DelegationTeam {
+            name: row.get(0),
+            sport: row.get(1),
+            gender: row.get(2),
+            uuid: row.get(3),
+            captain: row.get(4),
+            nb_members: row.get(5)
+        })
+        .collect();
+
+        let mut sport_names: Vec<&String> = event.sports.keys().collect();
+        sport_names.sort();
+
+        let mut slots: Vec<SportSlots> = vec![];
+        let mut without_team: Vec<SportWithoutTeam> = vec![];
+        for name in sport_names {
+            let rules = &event.sports[name];
+            let registered = teams.iter().filter(|t| t.sport == *name).count() as i64;
+            slots.push(SportSlots {
+                sport: name.clone(),
+                strict: matches!(rules.bounds, SportBounds::Strict { .. }),
+                max_teams: rules.max_teams_per_school,
+                registered,
+                remaining: (i64::from(rules.max_teams_per_school) - registered).max(0)
+            });
+
+            let missing: Vec<String> = athletes.iter()
+                .filter(|a| a.sports.contains(name))
+                .filter(|a| !memberships.get(&a.attendee_id).is_some_and(|teams| teams.iter().any(|(sport, _)| sport == name)))
+                .map(|a| format!("{} {} ({})", a.first_name, a.last_name, a.attendee_ref))
+                .collect();
+            if !missing.is_empty() {
+                without_team.push(SportWithoutTeam { sport: name.clone(), athletes: missing });
+            }
+        }
+
+        Ok(DelegationOverview { school, athletes, teams, slots, without_team })
+    }
+}
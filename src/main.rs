@@ -1,67 +1,85 @@
-/**
- * EAG HTTP REST API for team registration
- * 
- * Features :
- *  - Configuration via the teams.conf file
- *  - Works with attendize
- *  - Verification of individual participants : checks if the participant is not refunded and if he is a athlete
- *  - Team verification and registration
- * 
- * Made with the Rocket Rust Framework
- */
+//! EAG HTTP REST API for team registration
+//!
+//! Features :
+//!  - Configuration via the teams.conf file
+//!  - Works with attendize
+//!  - Verification of individual participants : checks if the participant is not refunded and if he is a athlete
+//!  - Team verification and registration
+//!
+//! Made with the Rocket Rust Framework
 
 #[macro_use]extern crate rocket;
 
 use std::path::{Path, PathBuf};
+use std::collections::HashMap;
 use async_process::Command;
 
-use rocket_db_pools::sqlx::Acquire;
 use rocket_db_pools::{sqlx, Database, Connection};
 
 pub mod config;
 pub mod defs;
 pub mod checks;
-
-use config::{find_sport, get_option};
+pub mod teams;
+pub mod auth;
+pub mod staff;
+pub mod delegation;
+pub mod audit;
+pub mod signups;
+pub mod meals;
+pub mod checkin;
+pub mod dashboard;
+
+use config::{AppConfig, EventConfig, LiveConfig, SharedConfig};
 use rocket::serde::json::Json;
 use rocket::response::Redirect;
 use rocket::fs::NamedFile;
-use rocket::Request;
+use rocket::{Request, State};
+use rocket::http::{CookieJar, Status};
+use rocket::fairing::AdHoc;
+use rocket::Shutdown;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
 
 use rocket_dyn_templates::{Template, context};
 
 use defs::*;
 use checks::*;
-
-use rocket_db_pools::sqlx::Row;
+use teams::*;
+use auth::*;
+use staff::*;
+use delegation::*;
+use audit::Actor;
+
+use rocket_db_pools::sqlx::{Acquire, Row};
+use rocket_db_pools::sqlx::mysql::MySqlConnection;
 use sqlx::types::chrono::NaiveDateTime;
 
 #[derive(Database, Clone)]
 #[database("attendize")]
 pub struct Attendize(sqlx::MySqlPool);
 
-/**
+/*
  * API routes
  */
 
-/**
+/*
  * ----- API PREFIX /attendee -----
  * 
  * Routes used for information on attendees
  */
 
-#[get("/attendee/sports/<order_ref>")]
-pub async fn get_attendee_sports(mut db: Connection<Attendize>, order_ref: &str) -> Option<Json<Vec<Sport>>> {
-    let attendee_opt = retrieve_attendee(&mut db, order_ref).await;
+#[get("/e/<event_slug>/attendee/sports/<order_ref>")]
+pub async fn get_attendee_sports(mut db: Connection<Attendize>, cfg: LiveConfig, event_slug: &str, order_ref: &str) -> Option<Json<Vec<Sport>>> {
+    let event = cfg.event(event_slug)?;
+    let attendee_opt = retrieve_attendee(&mut db, event, order_ref).await;
 
     match attendee_opt {
-        Ok(ida) => {
-            return Some(Json(ida.sports));
-        }
-        Err(_) => return None
+        Ok(ida) => Some(Json(ida.sports)),
+        Err(_) => None
     }
 }
-/**
+/*
 #[get("/attendee/<order_ref>")]
 pub async fn get_attendee(mut db: Connection<Attendize>, order_ref:&str) -> Option<Json<IdentifiedAttendee>> {
     match retrieve_attendee(&mut db, order_ref).await {
@@ -70,63 +88,56 @@ pub async fn get_attendee(mut db: Connection<Attendize>, order_ref:&str) -> Opti
     }
 } */
 
-#[get("/attendee/check/<team_sport>/<team_gender>/<order_ref>")]
-pub async fn get_check_attendee(mut db: Connection<Attendize>, team_sport: &str, team_gender: &str, order_ref: &str) -> Json<CheckAttendeeResponse> {
+#[get("/e/<event_slug>/attendee/check/<team_sport>/<team_gender>/<order_ref>")]
+pub async fn get_check_attendee(mut db: Connection<Attendize>, cfg: LiveConfig, event_slug: &str, team_sport: &str, team_gender: &str, order_ref: &str) -> Option<Json<CheckAttendeeResponse>> {
+    let event = cfg.event(event_slug)?;
     let mut response = CheckAttendeeResponse {
         message: String::from("Error : unhandled case"),
         member: None,
-        ticket_title: String::from("")
+        ticket_title: String::from(""),
+        entitlements: None
     };
 
-    let attendee = retrieve_attendee(&mut db, order_ref).await;
+    let attendee = retrieve_attendee(&mut db, event, order_ref).await;
 
     match attendee {
         Ok(id_attendee) => {
-            let gender: AttendeeGender;
-            match team_gender {
-                "M" => gender = AttendeeGender::M,
-                "Mixed" => gender = id_attendee.gender,
-                "F" => gender = AttendeeGender::F,
+            let gender: AttendeeGender = match team_gender {
+                "M" => AttendeeGender::M,
+                "Mixed" => id_attendee.gender,
+                "F" => AttendeeGender::F,
                 _other => {
                     response.message = String::from("Invalid gender option");
-                    return Json(response);
+                    return Some(Json(response));
                 }
-            }
+            };
 
-            let sport = find_sport(team_sport, Some(gender));
+            let sport = event.find_sport(team_sport, Some(gender));
             match sport {
                 Ok(_) => (),
                 Err(e) => {
                     response.message = format!("Error while reading sport: {e:?}");
-                    return Json(response);
+                    return Some(Json(response));
                 }
             }
 
-            let m = CompleteTeamMember::from_attendee_id(&mut *db, id_attendee.id).await.unwrap();
+            let m = CompleteTeamMember::from_attendee_id(&mut db, event, id_attendee.id).await.unwrap();
             let fullname = format!("{} {}", m.first_name, m.last_name);
+            let sport = sport.unwrap();
 
-            match validate_attendee(&mut db, &id_attendee, &sport.unwrap()).await {
-                AttendeeStatus::SportNotRegistered => 
-                    response.message = format!("{fullname} did not register in {team_sport}"),
-                AttendeeStatus::InvalidSport => 
-                    response.message = format!("{fullname} has an invalid sport name or sport is unavailable"),
-                AttendeeStatus::AlreadyInATeam =>
-                    response.message = format!("{fullname} is already in a {team_sport} team"),
-                AttendeeStatus::NotAnAthlete =>
-                    response.message = format!("{fullname} is a supporter, not an athlete"),
-                AttendeeStatus::InvalidGender =>
-                    response.message = String::from(format!("{team_sport} does not allow mixed teams, every team member must be of gender {team_gender}")),
+            match validate_attendee(&mut db, event, &id_attendee, &sport).await {
                 AttendeeStatus::Ok => {
                     response.member = Some(m);
                     response.message = String::from("Ok");
                 }
+                other => response.message = status_message(other, &fullname, &sport)
             }
         }
         Err(_) => {
             response.message = String::from("Attendee not found");
         }
     }
-    return Json(response);
+    Some(Json(response))
 }
 
 /**
@@ -135,154 +146,170 @@ pub async fn get_check_attendee(mut db: Connection<Attendize>, team_sport: &str,
  * Routes used to create and retrive information on teams
  */
 
-#[post("/team/create", format="json", data="<team>")]
-pub async fn post_create_team(mut db: Connection<Attendize>, team: Json<Team>) -> Json<SimpleResponse> {
-    let attendee_gender:Option<AttendeeGender>;
-    let mut response = SimpleResponse {
+#[post("/e/<event_slug>/team/create", format="json", data="<team>")]
+pub async fn post_create_team(mut db: Connection<Attendize>, cfg: LiveConfig, event_slug: &str, team: Json<Team>) -> Option<Json<TeamResponse>> {
+    let event = cfg.event(event_slug)?;
+    // The compose form is public, the captain reference is the only thing known of its user
+    let actor = Actor::Attendee(team.refs.first().cloned().unwrap_or_default());
+    Some(Json(register_team(&mut db, event, &team, &actor).await))
+}
+
+/**
+ * Validates and stores a team, shared by captains and delegation leaders
+ */
+async fn register_team(db: &mut MySqlConnection, event: &EventConfig, team: &Team, actor: &Actor) -> TeamResponse {
+    let mut response = TeamResponse {
         message: String::from("Unhandled case"),
-        code: SimpleResponseCode::ServerError
+        code: SimpleResponseCode::ServerError,
+        report: None,
+        uuid: None
     };
-    match team_registration_open() {
-        Ok(_) => (),
-        Err(_) => {
-            response.message = format!("It is not possible to register a team right now");
-            response.code = SimpleResponseCode::UserError;
-            return Json(response);
-        }
-    }
-    // Check number of team members
-    match team.gender {
-        SportGender::M => attendee_gender = Some(AttendeeGender::M),
-        SportGender::F => attendee_gender = Some(AttendeeGender::F),
-        SportGender::Mixed => attendee_gender = None
-    }
+
     let sport:Sport;
-    match config::find_sport(&team.sport, attendee_gender) {
-        Ok(s) => {
+    let attendee_list:Vec<IdentifiedAttendee>;
+    match check_team(&mut *db, event, team).await {
+        Ok((s, al)) => {
             sport = s;
-            let nb_members = team.refs.len();
-            if nb_members < usize::from(sport.min_players) || nb_members > usize::from(sport.max_players) {
-                let min = sport.min_players;
-                let max = sport.max_players;
-                response.message = format!("Invalid number of team members, is should be between {min} and {max} and not {nb_members}");
-                response.code = SimpleResponseCode::UserError;
-                return Json(response);
-            }
+            attendee_list = al;
         }
-        Err(_e) => {
-            response.message = String::from("Invalid \'sport\' field in JSON payload");
+        Err(report) => {
+            response.message = report.summary();
             response.code = SimpleResponseCode::UserError;
-            return Json(response);
+            response.report = Some(report);
+            return response;
         }
     }
 
-    let attendee_list:Vec<IdentifiedAttendee>;
-    match validate_team(&mut *db, &team, sport).await {
-        Ok(al) => attendee_list = al,
-        Err(e) => {
-            response.message = e;
+    match create_team(&mut *db, event, team, &sport, &attendee_list, actor).await {
+        Ok(team_id) => {
+            response.message = String::from("Team created");
+            response.code = SimpleResponseCode::Ok;
+            response.uuid = sqlx::query("SELECT uuid FROM teams WHERE id = ?")
+                .bind(team_id)
+                .fetch_one(&mut *db)
+                .await
+                .ok()
+                .map(|row| row.get(0));
+        }
+        Err(CreateTeamError::SchoolQuotaReached) => {
+            let mut report = TeamValidationReport::default();
+            report.add_team_problem(TeamProblemKind::SchoolQuota, school_quota_message(&sport));
+            response.message = report.summary();
             response.code = SimpleResponseCode::UserError;
-            return Json(response);
+            response.report = Some(report);
+        }
+        Err(CreateTeamError::AlreadyInATeam(attendee_id)) => {
+            // Attendees are listed in the order of the references
+            let position = attendee_list.iter().position(|a| a.id == attendee_id);
+            let reference = position.and_then(|i| team.refs.get(i)).map_or("", |r| r.as_str());
+            let name = attendee_name(&mut *db, attendee_id).await;
+            let message = status_message(AttendeeStatus::AlreadyInATeam, name.as_deref().unwrap_or(reference), &sport);
+            let mut report = TeamValidationReport::default();
+            report.add_reference_problem(reference, name, Some(AttendeeStatus::AlreadyInATeam), message);
+            response.message = report.summary();
+            response.code = SimpleResponseCode::UserError;
+            response.report = Some(report);
+        }
+        Err(CreateTeamError::Database(e)) => {
+            response.message = format!("DB error, rolling back : {e}");
+            response.code = SimpleResponseCode::ServerError;
         }
     }
+    response
+}
 
-    let captain_id = attendee_list[0].id;
-
-    // Create the new team
-    // Let this be a transaction, because of multiple INSERT statements
-    let mut tx = (&mut *db).begin().await.unwrap();
-    let res = sqlx::query("INSERT INTO teams(school_id, name, captain_id, uuid, sport, gender) VALUES (?,?,?,UUID(),?,?)")
-        .bind(&team.school_id)
-        .bind(&team.name)
-        .bind(captain_id)
-        .bind(&team.sport)
-        .bind(
-            match &team.gender {
-                SportGender::M => "Male",
-                SportGender::F => "Female",
-                SportGender::Mixed => "Mixed"
-            }
-        )
-        .execute(&mut tx).await;
-    let mut team_id:u64 = 0;
-    let mut mysql_errored = false;
-
-    match res {
-        Ok(r) => team_id = r.last_insert_id(),
-        Err(_e) => mysql_errored = true
+/**
+ * Team created by a delegation leader, always for their own school and with a captain from it
+ */
+#[post("/e/<event_slug>/delegation/team", format="json", data="<team>")]
+pub async fn post_delegation_team(mut db: Connection<Attendize>, cfg: LiveConfig, leader: DelegationLeader, _csrf: CsrfProtected, event_slug: &str, team: Json<Team>) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !leader.account.can_access(event) {
+        return Err(Status::Forbidden);
     }
-
-    // Add team members
-    if !mysql_errored {
-        for participant in attendee_list {
-            let insert = sqlx::query("INSERT INTO team_members(team_id, attendee_id) VALUES (?, ?)").bind(team_id).bind(participant.id).execute(&mut tx).await;
-            if insert.is_err() {
-                mysql_errored = true;
-            }
-        }
+    let captain_school = match team.refs.first() {
+        Some(captain_ref) => retrieve_attendee(&mut db, event, captain_ref).await.ok().map(|a| a.school_id),
+        None => None
+    };
+    if captain_school.is_some() && captain_school != Some(leader.school_id) {
+        let mut report = TeamValidationReport::default();
+        report.add_team_problem(TeamProblemKind::SchoolMix, String::from("The captain, first of the list, must be from your school"));
+        return Ok(Json(TeamResponse {
+            message: report.summary(),
+            code: SimpleResponseCode::UserError,
+            report: Some(report),
+            uuid: None
+        }));
     }
+    let actor = Actor::DelegationLeader(leader.account.username.clone());
+    Ok(Json(register_team(&mut db, event, &team, &actor).await))
+}
 
-    match mysql_errored {
-        true => {
-            response.code = SimpleResponseCode::ServerError;
-            match tx.rollback().await {
-                Ok(_) => response.message = String::from("DB error, rolling back"),
-                Err(e) => response.message = String::from(format!("Can\'t roll back : {e}"))
-            }
-        }
-        false => {
-            match tx.commit().await {
-                Ok(_) => {
-                    response.message = String::from("Team created");
-                    response.code = SimpleResponseCode::Ok;
-                },
-                Err(e) => {
-                    response.message = String::from(format!("DB commit error : {e}"));
-                    response.code = SimpleResponseCode::ServerError;
-                }
-            }
+/**
+ * Runs every check of team creation without registering the team
+ */
+#[post("/e/<event_slug>/team/validate", format="json", data="<team>")]
+pub async fn post_validate_team(mut db: Connection<Attendize>, cfg: LiveConfig, event_slug: &str, team: Json<Team>) -> Option<Json<TeamResponse>> {
+    let event = cfg.event(event_slug)?;
+    let response = match check_team(&mut db, event, &team).await {
+        Ok(_) => TeamResponse {
+            message: String::from("The team is valid"),
+            code: SimpleResponseCode::Ok,
+            report: Some(TeamValidationReport::default()),
+            uuid: None
+        },
+        Err(report) => TeamResponse {
+            message: report.summary(),
+            code: SimpleResponseCode::UserError,
+            report: Some(report),
+            uuid: None
         }
-    }
-    return Json(response);
+    };
+    Some(Json(response))
 }
 
-
-#[get("/team/can_register/<sport_name>/<order_ref>")]
-pub async fn get_can_register(mut db: Connection<Attendize>, sport_name: &str, order_ref: &str) -> Json<SimpleResponse>
+#[get("/e/<event_slug>/team/can_register/<sport_name>/<order_ref>")]
+pub async fn get_can_register(mut db: Connection<Attendize>, cfg: LiveConfig, event_slug: &str, sport_name: &str, order_ref: &str) -> Option<Json<SimpleResponse>>
 {
+    let event = cfg.event(event_slug)?;
     let mut response = SimpleResponse {
         message: String::from("Unhandled case"),
         code: SimpleResponseCode::ServerError
     };
 
-    let captain: IdentifiedAttendee;
-    match retrieve_attendee(&mut *db, order_ref).await {
-        Ok(a) => captain = a,
+    let captain: IdentifiedAttendee = match retrieve_attendee(&mut db, event, order_ref).await {
+        Ok(a) => a,
         Err(_) => {
             response.message = String::from("Attendee not found");
             response.code = SimpleResponseCode::UserError;
-            return Json(response);
+            return Some(Json(response));
         }
+    };
+
+    if let Some(message) = event.registration_closed(sport_name) {
+        response.message = message;
+        response.code = SimpleResponseCode::UserError;
+        return Some(Json(response));
     }
 
-    match config::find_sport(sport_name, Some(captain.gender)) {
+    match event.find_sport(sport_name, Some(captain.gender)) {
         Ok(sport) => {
-            match can_school_register_team(&mut *db, &captain, &sport).await {
+            match can_school_register_team(&mut db, event, captain.school_id, &sport).await {
                 true => {
                     response.message = String::from("Ok");
                     response.code = SimpleResponseCode::Ok;
                 }
                 false => {
-                    response.message = String::from(format!(
+                    response.message = format!(
                         "Your school has already registered {max_teams_per_school} teams in this sport, in {sport_name} it is not possible to register more teams", 
                         max_teams_per_school = sport.max_teams_per_school, 
-                        sport_name = sport.name)
+                        sport_name = sport.name
                     );
                     response.code = SimpleResponseCode::UserError;
-                    return Json(response);
+                    return Some(Json(response));
                 }
             }
-            match validate_attendee(&mut *db, &captain, &sport).await {
+            match validate_attendee(&mut db, event, &captain, &sport).await {
                 AttendeeStatus::Ok => {
                     response.message = String::from("Ok");
                     response.code = SimpleResponseCode::Ok;
@@ -299,130 +326,363 @@ pub async fn get_can_register(mut db: Connection<Attendize>, sport_name: &str, o
             response.code = SimpleResponseCode::UserError;
         }
     }
-    return Json(response);
+    Some(Json(response))
 }
 
-#[get("/team/edit/<uuid>/add/<order_ref>")]
-pub async fn get_add_team_member(mut db: Connection<Attendize>, uuid:&str, order_ref: &str) -> Json<CheckAttendeeResponse> {
-    let mut response = CheckAttendeeResponse{
-        message: format!("Unknown error"),
-        member: None,
-        ticket_title: String::from("")
-    };
-    match retrieve_attendee(&mut *db, order_ref).await {
-        Ok(ida) => {
-            let res = sqlx::query(
-                "SELECT id, sport FROM teams WHERE uuid = ?"
-            )
-            .bind(uuid)
-            .fetch_optional(&mut *db)
-            .await
-            .unwrap();
-
-            if res.is_none() {
-                response.message = format!("Team not found");
-                return Json(response);
-            }
-
-            let row = res.unwrap();
-            let team_id:u32 = row.get(0);
-            let team_sport:&str = row.get(1);
-            let sport = find_sport(team_sport, Some(ida.gender)).unwrap();
-            let status = validate_attendee(&mut *db, &ida, &sport).await;
-
-            if status == AttendeeStatus::Ok {
-                let member = CompleteTeamMember::from_attendee_id(&mut *db, ida.id).await.unwrap();
-                let res = sqlx::query(
-                    "INSERT INTO team_members(attendee_id, team_id) VALUES (?, ?)"
-                )
-                .bind(ida.id)
-                .bind(team_id)
-                .execute(&mut *db).await;
-                
-                match res {
-                    Ok(_) => {
-                        response.message = format!("Ok");
-                        response.member = Some(member);
-                        return Json(response);
-                    },
-                    Err(e) => {
-                        response.message = format!("Team insert error : {:?}", e);
-                        return Json(response);
-                    }
-                }
-            }
-            else {
-                response.message = format!("Error : {:?}", status);
-                return Json(response);
-            }
+/**
+ * Outcome of a roster edit as returned to the team page
+ */
+fn team_edit_response(result: Result<(), EditTeamError>, success: &str) -> TeamResponse {
+    match result {
+        Ok(()) => TeamResponse {
+            message: String::from(success),
+            code: SimpleResponseCode::Ok,
+            report: None,
+            uuid: None
         },
-        Err(_) => {
-            response.message = format!("Attendee not found");
-            return Json(response);
+        Err(EditTeamError::TeamNotFound) => TeamResponse {
+            message: String::from("Team not found"),
+            code: SimpleResponseCode::UserError,
+            report: None,
+            uuid: None
+        },
+        Err(EditTeamError::RequestNotFound) => TeamResponse {
+            message: String::from("This request does not exist or was already answered"),
+            code: SimpleResponseCode::UserError,
+            report: None,
+            uuid: None
+        },
+        Err(EditTeamError::Invalid(report)) => TeamResponse {
+            message: report.summary(),
+            code: SimpleResponseCode::UserError,
+            report: Some(report),
+            uuid: None
+        },
+        Err(EditTeamError::Database(e)) => TeamResponse {
+            message: format!("DB error, rolling back : {e}"),
+            code: SimpleResponseCode::ServerError,
+            report: None,
+            uuid: None
+        }
+    }
+}
+
+#[post("/e/<event_slug>/team/<uuid>/members/<order_ref>")]
+pub async fn post_team_member(mut db: Connection<Attendize>, cfg: LiveConfig, editor: Editor, _csrf: CsrfProtected, event_slug: &str, uuid: &str, order_ref: &str) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !editor.can_edit(&mut db, event, uuid).await {
+        return Err(Status::Forbidden);
+    }
+    let actor = editor.actor(event).ok_or(Status::Forbidden)?;
+    let response = match retrieve_attendee(&mut db, event, order_ref).await {
+        Ok(ida) => team_edit_response(add_team_member(&mut db, event, uuid, &ida, order_ref, &actor).await, "Member added"),
+        Err(e) => TeamResponse {
+            message: format!("Attendee not found : {e}"),
+            code: SimpleResponseCode::UserError,
+            report: None,
+            uuid: None
+        }
+    };
+    Ok(Json(response))
+}
+
+#[delete("/e/<event_slug>/team/<uuid>/members/<order_ref>")]
+pub async fn delete_team_member(mut db: Connection<Attendize>, cfg: LiveConfig, editor: Editor, _csrf: CsrfProtected, event_slug: &str, uuid: &str, order_ref: &str) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !editor.can_edit(&mut db, event, uuid).await {
+        return Err(Status::Forbidden);
+    }
+    let actor = editor.actor(event).ok_or(Status::Forbidden)?;
+    let response = match retrieve_attendee(&mut db, event, order_ref).await {
+        Ok(ida) => team_edit_response(remove_team_member(&mut db, event, uuid, &ida, order_ref, &actor).await, "Member removed"),
+        Err(e) => TeamResponse {
+            message: format!("Attendee not found : {e}"),
+            code: SimpleResponseCode::UserError,
+            report: None,
+            uuid: None
+        }
+    };
+    Ok(Json(response))
+}
+
+#[put("/e/<event_slug>/team/<uuid>/name", format="json", data="<rename>")]
+pub async fn put_team_name(mut db: Connection<Attendize>, cfg: LiveConfig, editor: Editor, _csrf: CsrfProtected, event_slug: &str, uuid: &str, rename: Json<TeamRename>) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !editor.can_edit(&mut db, event, uuid).await {
+        return Err(Status::Forbidden);
+    }
+    let actor = editor.actor(event).ok_or(Status::Forbidden)?;
+    let name = rename.name.trim();
+    Ok(Json(team_edit_response(rename_team(&mut db, event, uuid, name, &actor).await, "Team renamed")))
+}
+
+/**
+ * Gives the captaincy to another member, for instance when the captain cancelled their ticket
+ */
+#[put("/e/<event_slug>/team/<uuid>/captain/<order_ref>")]
+pub async fn put_team_captain(mut db: Connection<Attendize>, cfg: LiveConfig, editor: Editor, _csrf: CsrfProtected, event_slug: &str, uuid: &str, order_ref: &str) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !editor.can_edit(&mut db, event, uuid).await {
+        return Err(Status::Forbidden);
+    }
+    let actor = editor.actor(event).ok_or(Status::Forbidden)?;
+    // Cancelled attendees are not found, so the new captain is always an active attendee
+    let response = match retrieve_attendee(&mut db, event, order_ref).await {
+        Ok(ida) => team_edit_response(transfer_captaincy(&mut db, event, uuid, &ida, order_ref, &actor).await, "Captain changed"),
+        Err(e) => TeamResponse {
+            message: format!("Attendee not found : {e}"),
+            code: SimpleResponseCode::UserError,
+            report: None,
+            uuid: None
+        }
+    };
+    Ok(Json(response))
+}
+
+/**
+ * Dissolves a team, its members become free to join another team of the sport
+ */
+#[delete("/e/<event_slug>/team/<uuid>")]
+pub async fn delete_team_route(mut db: Connection<Attendize>, cfg: LiveConfig, editor: Editor, _csrf: CsrfProtected, event_slug: &str, uuid: &str) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !editor.can_edit(&mut db, event, uuid).await {
+        return Err(Status::Forbidden);
+    }
+    let actor = editor.actor(event).ok_or(Status::Forbidden)?;
+    Ok(Json(team_edit_response(delete_team(&mut db, event, uuid, &actor).await, "Team deleted")))
+}
+
+/**
+ * Brings a deleted team back with its members
+ */
+#[post("/e/<event_slug>/team/<uuid>/restore")]
+pub async fn post_restore_team(mut db: Connection<Attendize>, cfg: LiveConfig, admin: SportAdmin, _csrf: CsrfProtected, event_slug: &str, uuid: &str) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !admin.0.can_access(event) {
+        return Err(Status::Forbidden);
+    }
+    let actor = Actor::Staff(admin.0.username.clone());
+    Ok(Json(team_edit_response(restore_team(&mut db, event, uuid, &actor).await, "Team restored")))
+}
+
+/**
+ * Asks to join the team of a join code, the captain then accepts or declines
+ */
+#[post("/e/<event_slug>/join/<join_code>/<order_ref>")]
+pub async fn post_join_request(mut db: Connection<Attendize>, cfg: LiveConfig, _csrf: CsrfProtected, event_slug: &str, join_code: &str, order_ref: &str) -> Option<Json<TeamResponse>> {
+    let event = cfg.event(event_slug)?;
+    let actor = Actor::Attendee(String::from(order_ref));
+    let response = match retrieve_attendee(&mut db, event, order_ref).await {
+        Ok(ida) => team_edit_response(
+            request_to_join(&mut db, event, join_code, &ida, order_ref, &actor).await,
+            "Your request was sent to the captain of the team"
+        ),
+        Err(e) => TeamResponse {
+            message: format!("Attendee not found : {e}"),
+            code: SimpleResponseCode::UserError,
+            report: None,
+            uuid: None
         }
+    };
+    Some(Json(response))
+}
+
+#[post("/e/<event_slug>/team/<uuid>/requests/<request_id>/accept")]
+pub async fn post_accept_join_request(mut db: Connection<Attendize>, cfg: LiveConfig, editor: Editor, _csrf: CsrfProtected, event_slug: &str, uuid: &str, request_id: u32) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !editor.can_edit(&mut db, event, uuid).await {
+        return Err(Status::Forbidden);
+    }
+    let actor = editor.actor(event).ok_or(Status::Forbidden)?;
+    Ok(Json(team_edit_response(accept_join_request(&mut db, event, uuid, request_id, &actor).await, "Member added")))
+}
+
+#[post("/e/<event_slug>/team/<uuid>/requests/<request_id>/decline")]
+pub async fn post_decline_join_request(mut db: Connection<Attendize>, cfg: LiveConfig, editor: Editor, _csrf: CsrfProtected, event_slug: &str, uuid: &str, request_id: u32) -> Result<Json<TeamResponse>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !editor.can_edit(&mut db, event, uuid).await {
+        return Err(Status::Forbidden);
     }
+    let actor = editor.actor(event).ok_or(Status::Forbidden)?;
+    Ok(Json(team_edit_response(decline_join_request(&mut db, event, uuid, request_id, &actor).await, "Request declined")))
 }
 
-#[get("/team/edit/<uuid>/del/<order_ref>")]
-pub async fn get_del_team_member(mut db: Connection<Attendize>, uuid: &str, order_ref: &str) -> Json<SimpleResponse> {
+/**
+ * Opens a captain session for the team, the captain proves who they are
+ * with the number of the QR code of their ticket
+ */
+#[post("/e/<event_slug>/team/<uuid>/captain-login", format="json", data="<login>")]
+pub async fn post_captain_login(mut db: Connection<Attendize>, cfg: LiveConfig, cookies: &CookieJar<'_>, _csrf: CsrfProtected, event_slug: &str, uuid: &str, login: Json<CaptainLogin>) -> Option<Json<SimpleResponse>> {
+    let event = cfg.event(event_slug)?;
     let mut response = SimpleResponse {
-        code: SimpleResponseCode::ServerError,
-        message: format!("Unknown error")
-    };
-    match retrieve_attendee(&mut *db, order_ref).await {
-        Ok(ida) => {
-            let res = sqlx::query(
-                "SELECT id FROM teams WHERE uuid = ?"
-            )
-            .bind(uuid)
-            .fetch_optional(&mut *db)
-            .await
-            .unwrap();
-
-            if res.is_none() {
-                response.code = SimpleResponseCode::UserError;
-                response.message = format!("Team not found");
-                return Json(response);
-            }
+        message: String::from("This ticket number is not the one of the captain"),
+        code: SimpleResponseCode::UserError
+    };
+    let captain = sqlx::query(
+        "SELECT a.id FROM teams t JOIN attendees a ON t.captain_id = a.id
+        WHERE t.uuid = ? AND t.event_id = ? AND t.deleted_at IS NULL
+        AND a.is_cancelled = 0 AND a.private_reference_number = ?"
+    )
+    .bind(uuid)
+    .bind(event.event_id)
+    .bind(login.ticket_number.trim())
+    .fetch_optional(&mut *db)
+    .await;
+
+    match captain {
+        Ok(Some(row)) => {
+            login_captain(cookies, event, row.get(0));
+            response.message = String::from("Ok");
+            response.code = SimpleResponseCode::Ok;
+        }
+        Ok(None) => (),
+        Err(e) => {
+            response.message = format!("DB error : {e}");
+            response.code = SimpleResponseCode::ServerError;
+        }
+    }
+    Some(Json(response))
+}
+
+#[post("/logout")]
+pub fn post_logout(cookies: &CookieJar<'_>, _csrf: CsrfProtected) -> Json<SimpleResponse> {
+    logout(cookies);
+    Json(SimpleResponse {
+        message: String::from("Ok"),
+        code: SimpleResponseCode::Ok
+    })
+}
 
-            let team_id:u32 = res.unwrap().get(0);
+/**
+ * Opens a staff session, accounts are created by a sport admin
+ */
+#[post("/staff/login", format="json", data="<login>")]
+pub async fn post_staff_login(mut db: Connection<Attendize>, cookies: &CookieJar<'_>, _csrf: CsrfProtected, login: Json<StaffLogin>) -> Json<SimpleResponse> {
+    match StaffAccount::authenticate(&mut db, &login.username, &login.password).await {
+        Some(account) => {
+            login_staff(cookies, &account);
+            info!("Staff login : {} ({})", account.username, account.role.stored_name());
+            Json(SimpleResponse {
+                message: String::from("Ok"),
+                code: SimpleResponseCode::Ok
+            })
+        }
+        None => Json(SimpleResponse {
+            message: String::from("Invalid username or password"),
+            code: SimpleResponseCode::UserError
+        })
+    }
+}
 
-            let delete_res = sqlx::query(
-                "DELETE FROM team_members WHERE team_id = ? AND attendee_id = ?"
-            )
-            .bind(team_id)
-            .bind(ida.id)
-            .execute(&mut *db).await;
+/*
+ * ----- API PREFIX /admin
+ *
+ * Routes used by the sport team to operate the running server
+ */
 
-            match delete_res {
-                Ok(_) => {
-                    response.message = String::from("Ok");
-                    response.code = SimpleResponseCode::Ok;
-                },
-                Err(e) => {
-                    response.message = format!("Could not delete member : {:?}", e);
-                }
-            }
-            
-            return Json(response);
+/**
+ * Re-reads the configuration file, the new one is used only if it is entirely valid
+ */
+#[post("/admin/reload-config")]
+pub async fn post_reload_config(config: &State<SharedConfig>, admin: SportAdmin, _csrf: CsrfProtected) -> Option<Json<SimpleResponse>> {
+    // The configuration is shared by every event
+    if admin.0.event_id.is_some() {
+        return None;
+    }
+    let response = match config.reload() {
+        Ok(changes) if changes.is_empty() => SimpleResponse {
+            message: String::from("Configuration reloaded, nothing changed"),
+            code: SimpleResponseCode::Ok
         },
-        Err(e) => {
-            response.code = SimpleResponseCode::UserError;
-            response.message = format!("Attendee not found : {e}");
-            return Json(response);
+        Ok(changes) => SimpleResponse {
+            message: format!("Configuration reloaded : {}", changes.join(", ")),
+            code: SimpleResponseCode::Ok
+        },
+        Err(errors) => SimpleResponse {
+            message: format!("Configuration not reloaded, the current one is kept : {}", errors.join(", ")),
+            code: SimpleResponseCode::UserError
+        }
+    };
+    Some(Json(response))
+}
+
+/**
+ * Creates a staff account, sport admins limited to an event can only create accounts for it
+ */
+#[post("/admin/staff", format="json", data="<account>")]
+pub async fn post_create_staff_account(mut db: Connection<Attendize>, cfg: LiveConfig, admin: SportAdmin, _csrf: CsrfProtected, account: Json<NewStaffAccount>) -> Result<Json<SimpleResponse>, Status> {
+    let event_id = match &account.event {
+        Some(slug) => Some(cfg.event(slug).ok_or(Status::NotFound)?.event_id),
+        None => None
+    };
+    if admin.0.event_id.is_some() && admin.0.event_id != event_id {
+        return Err(Status::Forbidden);
+    }
+    let response = match create_account(&mut db, &account, event_id).await {
+        Ok(_) => {
+            info!("Staff account {} ({}) created by {}", account.username, account.role.stored_name(), admin.0.username);
+            SimpleResponse {
+                message: String::from("Account created"),
+                code: SimpleResponseCode::Ok
+            }
+        }
+        Err(e) => SimpleResponse {
+            message: e,
+            code: SimpleResponseCode::UserError
         }
+    };
+    Ok(Json(response))
+}
+
+#[post("/admin/staff/<id>/disable")]
+pub async fn post_disable_staff_account(db: Connection<Attendize>, admin: SportAdmin, _csrf: CsrfProtected, id: u32) -> Result<Json<SimpleResponse>, Status> {
+    if admin.0.id == id {
+        return Ok(Json(SimpleResponse {
+            message: String::from("You cannot disable your own account"),
+            code: SimpleResponseCode::UserError
+        }));
     }
+    staff_account_access(db, admin, id, true).await
+}
+
+#[post("/admin/staff/<id>/enable")]
+pub async fn post_enable_staff_account(db: Connection<Attendize>, admin: SportAdmin, _csrf: CsrfProtected, id: u32) -> Result<Json<SimpleResponse>, Status> {
+    staff_account_access(db, admin, id, false).await
 }
 
 /**
+ * Revokes or grants back the access of an account, sessions of a disabled account stop working at once
+ */
+async fn staff_account_access(mut db: Connection<Attendize>, admin: SportAdmin, id: u32, disabled: bool) -> Result<Json<SimpleResponse>, Status> {
+    let event_id = account_event_id(&mut db, id).await.ok_or(Status::NotFound)?;
+    if admin.0.event_id.is_some() && admin.0.event_id != event_id {
+        return Err(Status::Forbidden);
+    }
+    let response = match set_account_disabled(&mut db, id, disabled).await {
+        Ok(true) => {
+            info!("Staff account {id} {} by {}", if disabled { "disabled" } else { "enabled" }, admin.0.username);
+            SimpleResponse {
+                message: String::from("Ok"),
+                code: SimpleResponseCode::Ok
+            }
+        }
+        Ok(false) => return Err(Status::NotFound),
+        Err(e) => SimpleResponse {
+            message: e,
+            code: SimpleResponseCode::ServerError
+        }
+    };
+    Ok(Json(response))
+}
+
+/*
  * Web routes
  */
 
-pub fn team_registration_open() -> Result<(), Template> {
-    let val:bool = config::get_option("team_registration_open").parse().unwrap();
-    match val {
+pub fn team_registration_open(event: &EventConfig) -> Result<(), Box<Template>> {
+    match event.team_registration_open {
         true => Ok(()),
-        false => Err(Template::render("error", context!{message:"It is currently not possible to register a team"}))
+        false => Err(Box::new(Template::render("error", context!{message:"It is currently not possible to register a team"})))
     }
 }
 
@@ -436,46 +696,51 @@ pub async fn get_ressource(path: PathBuf) -> Option<NamedFile> {
     NamedFile::open(Path::new("ressources/").join(path)).await.ok()
 }
 
-#[get("/welcome/<order_ref>")]
-pub async fn get_welcome(mut db: Connection<Attendize>, order_ref: &str) -> Option<Template> {
-    match team_registration_open() {
+#[get("/e/<event_slug>/welcome/<order_ref>")]
+pub async fn get_welcome(mut db: Connection<Attendize>, cfg: LiveConfig, event_slug: &str, order_ref: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    match team_registration_open(event) {
         Ok(_) => (),
-        Err(t) => return Some(t)
+        Err(t) => return Some(*t)
     }
-    match retrieve_attendee(&mut *db, order_ref).await {
+    match retrieve_attendee(&mut db, event, order_ref).await {
         Ok(attendee) => {
-            let context = context! {sports: attendee.sports, order_ref: order_ref};
+            // Sports whose registration window is not open, with the reason
+            let closed: HashMap<String, String> = attendee.sports.iter()
+                .filter_map(|s| Some((s.name.clone(), event.registration_closed(&s.name)?)))
+                .collect();
+            let context = context! {sports: attendee.sports, closed, order_ref: order_ref, event: event_slug, event_name: &event.name};
             Some(Template::render("welcome", &context))
         }
         Err(_) => None
     }
 }
 
-/**
+/*
  * ------ Team routes ------
  */
 
 /**
  * Page where user compose their team
  */
-#[get("/compose/<order_ref>/<sport_name>")]
-pub async fn get_compose(mut db: Connection<Attendize>, order_ref: &str, sport_name: &str) -> Option<Template> {
-    match team_registration_open() {
-        Ok(_) => (),
-        Err(t) => return Some(t)
+#[get("/e/<event_slug>/compose/<order_ref>/<sport_name>")]
+pub async fn get_compose(mut db: Connection<Attendize>, cfg: LiveConfig, event_slug: &str, order_ref: &str, sport_name: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if let Some(message) = event.registration_closed(sport_name) {
+        return Some(Template::render("error", context!{message}));
     }
 
-    match retrieve_attendee(&mut *db, order_ref).await {
+    match retrieve_attendee(&mut db, event, order_ref).await {
         Ok(id_attendee) => {
-            match find_sport(sport_name, Some(id_attendee.gender)) {
+            match event.find_sport(sport_name, Some(id_attendee.gender)) {
                 Ok(sport) => {
-                    match validate_attendee(&mut *db, &id_attendee, &sport).await {
+                    match validate_attendee(&mut db, event, &id_attendee, &sport).await {
                         AttendeeStatus::Ok => {
                             let context = context! {
-                                captain: CompleteTeamMember::from_attendee_id(&mut *db, id_attendee.id).await,
+                                captain: CompleteTeamMember::from_attendee_id(&mut db, event, id_attendee.id).await,
                                 sport: sport,
                                 captain_ref: order_ref,
-                                school_id: id_attendee.school_id
+                                event: event_slug
                             };
                             Some(Template::render("compose_team", &context))
                         },
@@ -516,95 +781,177 @@ fn not_found(req: &Request) -> Json<SimpleResponse> {
     })
 }
 
+#[catch(401)]
+fn unauthorized() -> Json<SimpleResponse> {
+    Json(SimpleResponse{
+        message: String::from("Please log in first"),
+        code: SimpleResponseCode::UserError
+    })
+}
+
+#[catch(403)]
+fn forbidden() -> Json<SimpleResponse> {
+    Json(SimpleResponse{
+        message: String::from("You are not allowed to do this"),
+        code: SimpleResponseCode::UserError
+    })
+}
+
+/**
+ * Staff pages send to the login page, which comes back to them once logged in
+ */
+#[catch(401)]
+fn staff_login_redirect(req: &Request) -> Redirect {
+    Redirect::to(uri!(get_staff_login(Some(req.uri().to_string()))))
+}
+
 /**
  * ----- TEAM PREVIEW ----------
  */
-#[get("/teams/<secret>?<school>&<sport>")]
-pub async fn get_list_teams(mut db: Connection<Attendize>, secret:&str, school:Option<u32>, sport:Option<String>) -> Option<Template> {
-    let cfg_secret = get_option("sport_secret");
-    if cfg_secret.as_str() != secret {
+#[get("/e/<event_slug>/teams")]
+pub async fn get_list_teams(mut db: Connection<Attendize>, cfg: LiveConfig, staff: SportStaff, event_slug: &str, filter: TeamListFilter) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
         return None;
     }
-    let res:Result<Vec<rocket_db_pools::sqlx::mysql::MySqlRow>, rocket_db_pools::sqlx::Error>;
-    if school.is_some() && sport.is_some() {
-        res = sqlx::query(
-            "SELECT qo.name school, t.name, t.sport, t.gender, t.uuid
-            FROM teams t JOIN question_options qo ON t.school_id = qo.id 
-            WHERE school_id = ? AND sport = ?
-            ORDER BY school"
-        )
-        .bind(school.unwrap())
-        .bind(sport.unwrap())
-        .fetch_all(&mut *db)
-        .await;
-    }
-    else if school.is_some() {
-        res = sqlx::query(
-            "SELECT qo.name school, t.name, t.sport, t.gender, t.uuid
-            FROM teams t JOIN question_options qo ON t.school_id = qo.id 
-            WHERE school_id = ?
-            ORDER BY school"
-        )
-        .bind(school.unwrap())
-        .fetch_all(&mut *db)
-        .await;
-    }
-    else if sport.is_some() {
-        res = sqlx::query(
-            "SELECT qo.name school, t.name, t.sport, t.gender, t.uuid
-            FROM teams t JOIN question_options qo ON t.school_id = qo.id 
-            WHERE sport = ?
-            ORDER BY school"
-        )
-        .bind(sport.unwrap())
-        .fetch_all(&mut *db)
-        .await;
-    }
-    else {
-        res = sqlx::query(
-            "SELECT qo.name school, t.name, t.sport, t.gender, t.uuid
-            FROM teams t JOIN question_options qo ON t.school_id = qo.id 
-            ORDER BY school"
-        )
-        .fetch_all(&mut *db)
-        .await;
-    }
-    let sports_fut = sqlx::query(
-        "SELECT name FROM question_options WHERE question_id IN (5,6,8) ORDER BY name"
-    ).fetch_all(&mut *db);
+    let sports_stmt = format!(
+        "SELECT name FROM question_options WHERE question_id IN {} ORDER BY name",
+        event.all_sport_question_ids()
+    );
+    let res = list_teams(&mut db, event, &filter).await;
+    let sports_res = sqlx::query(&sports_stmt).fetch_all(&mut *db).await;
+    let schools_res = sqlx::query(
+        "SELECT id, name FROM question_options WHERE question_id = ? ORDER BY name"
+    )
+    .bind(event.school_question_id)
+    .fetch_all(&mut *db)
+    .await;
     match res {
-        Ok(rows) => {
-            let mut teams:Vec<TeamView> = vec![];
-            for row in rows {
-                teams.push(TeamView { 
-                    name: row.get(1), 
-                    school: row.get(0), 
-                    sport: row.get(2), 
-                    gender: row.get(3), 
-                    uuid: row.get(4) });
-            }
+        Ok(teams) => {
             let mut sports:Vec<String> = vec![];
-            for row in sports_fut.await.ok()? {
+            for row in sports_res.ok()? {
                 sports.push(row.get(0));
             }
-            let ctx = context!{teams: teams, sports: sports};
-            return Some(Template::render("team_list", &ctx));
+            let mut schools:Vec<School> = vec![];
+            for row in schools_res.ok()? {
+                schools.push(School { id: row.get(0), name: row.get(1) });
+            }
+            let ctx = context!{teams: teams, sports: sports, schools: schools, filter, event: event_slug};
+            Some(Template::render("team_list", &ctx))
         },
-        Err(_) => {
-            return None;
+        Err(e) => {
+            error!("Unable to list the teams : {e}");
+            None
         }
     }
 }
 
-#[get("/no-team/list/<secret>")]
-pub async fn get_no_team_list(mut db: Connection<Attendize>, secret: &str) -> Option<Template> {
-    let cfg_secret = get_option("sport_secret");
-    if cfg_secret.as_str() != secret {
+/**
+ * Deleted teams, sport admins can restore them from here
+ */
+#[get("/e/<event_slug>/teams/deleted")]
+pub async fn get_deleted_teams(mut db: Connection<Attendize>, cfg: LiveConfig, staff: SportStaff, cookies: &CookieJar<'_>, event_slug: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
         return None;
     }
-    let sports_fut = sqlx::query(
-        "SELECT name FROM question_options WHERE question_id IN (5,6,8) ORDER BY name"
+    let rows = sqlx::query(
+        "SELECT t.name, qo.name school, t.sport, t.gender, t.uuid, DATE_FORMAT(t.deleted_at, '%Y-%m-%d %H:%i'), t.deleted_by, COUNT(dtm.id)
+        FROM teams t
+        JOIN question_options qo ON t.school_id = qo.id
+        LEFT JOIN deleted_team_members dtm ON dtm.team_id = t.id
+        WHERE t.event_id = ? AND t.deleted_at IS NOT NULL
+        GROUP BY t.id, t.name, qo.name, t.sport, t.gender, t.uuid, t.deleted_at, t.deleted_by
+        ORDER BY t.deleted_at DESC"
     )
+    .bind(event.event_id)
+    .fetch_all(&mut *db)
+    .await
+    .ok()?;
+    let teams: Vec<DeletedTeamView> = rows.iter().map(|row| DeletedTeamView {
+        name: row.get(0),
+        school: row.get(1),
+        sport: row.get(2),
+        gender: row.get(3),
+        uuid: row.get(4),
+        deleted_at: row.get(5),
+        deleted_by: row.get(6),
+        nb_members: row.get(7)
+    }).collect();
+    let can_restore = staff.0.role == StaffRole::SportAdmin;
+    let csrf_token = csrf_token(cookies);
+    Some(Template::render("deleted_teams", context!{teams, event: event_slug, can_restore, csrf_token}))
+}
+
+/**
+ * Audit log of the event, filtered by team uuid, attendee reference and time range
+ */
+#[get("/e/<event_slug>/audit")]
+pub async fn get_audit_log(mut db: Connection<Attendize>, cfg: LiveConfig, staff: SportStaff, event_slug: &str, query: audit::AuditQuery) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
+        return None;
+    }
+    let entries = match audit::search(&mut db, event, &query.filter()).await {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Unable to read the audit log : {e}");
+            return None;
+        }
+    };
+    Some(Template::render("audit_log", context!{entries, event: event_slug, query}))
+}
+
+/**
+ * Confirmed athletes and waiting lists of the sports with a capacity
+ */
+#[get("/e/<event_slug>/signups")]
+pub async fn get_signup_lists(mut db: Connection<Attendize>, cfg: LiveConfig, staff: SportStaff, event_slug: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
+        return None;
+    }
+    let lists = match signups::signup_lists(&mut db, event).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Unable to list the sign-ups : {e}");
+            return None;
+        }
+    };
+    Some(Template::render("signup_lists", context!{lists, event: event_slug}))
+}
+
+/**
+ * Portal of a delegation leader : athletes, teams and remaining team slots of their school
+ */
+#[get("/e/<event_slug>/delegation")]
+pub async fn get_delegation(mut db: Connection<Attendize>, cfg: LiveConfig, leader: DelegationLeader, cookies: &CookieJar<'_>, event_slug: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !leader.account.can_access(event) {
+        return None;
+    }
+    let overview = match DelegationOverview::load(&mut db, event, leader.school_id).await {
+        Ok(o) => o,
+        Err(e) => {
+            error!("Unable to load the delegation of school {} : {e}", leader.school_id);
+            return None;
+        }
+    };
+    let csrf_token = csrf_token(cookies);
+    Some(Template::render("delegation", context!{overview, event: event_slug, event_name: &event.name, csrf_token}))
+}
+
+#[get("/e/<event_slug>/no-team/list")]
+pub async fn get_no_team_list(mut db: Connection<Attendize>, cfg: LiveConfig, staff: SportStaff, event_slug: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
+        return None;
+    }
+    let sports_stmt = format!(
+        "SELECT name FROM question_options WHERE question_id IN {} ORDER BY name",
+        event.all_sport_question_ids()
+    );
+    let sports_fut = sqlx::query(&sports_stmt)
     .fetch_all(&mut *db);
 
     let mut sports:Vec<String> = vec![];
@@ -612,18 +959,19 @@ pub async fn get_no_team_list(mut db: Connection<Attendize>, secret: &str) -> Op
         sports.push(row.get(0));
     }
     Some(
-        Template::render("no_team_list", context!{sports: sports, secret: secret})
+        Template::render("no_team_list", context!{sports: sports, event: event_slug})
     )
 }
 
-#[get("/no-team/members/<sport>/<secret>")]
-pub async fn get_no_team(mut db: Connection<Attendize>, secret: &str, sport: &str) -> Option<Template> {
-    let cfg_secret = get_option("sport_secret");
-    if cfg_secret.as_str() != secret {
+#[get("/e/<event_slug>/no-team/members/<sport>")]
+pub async fn get_no_team(mut db: Connection<Attendize>, cfg: LiveConfig, staff: SportStaff, event_slug: &str, sport: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
         return None;
     }
     let mut members:Vec<CompleteTeamMember> = vec![];
-    let members_qry = sqlx::query(
+    let sport_question_ids = event.all_sport_question_ids();
+    let members_stmt = format!(
         "SELECT a.id, a.first_name, a.last_name, a.email, qb.answer_text school, qc.answer_text phone, qd.answer_text gender,
         CONCAT(o.order_reference, '-', a.reference_index) attendee_ref
         FROM attendees a
@@ -632,19 +980,25 @@ pub async fn get_no_team(mut db: Connection<Attendize>, secret: &str, sport: &st
         JOIN question_answers qc ON qc.attendee_id = a.id
         JOIN question_answers qd ON qd.attendee_id = a.id
         JOIN orders o ON a.order_id = o.id
-        WHERE a.event_id = 2 AND a.is_cancelled = 0
-        AND qa.question_id IN (5, 6, 7, 8) AND qa.answer_text = ?
-        AND qb.question_id = 15
+        WHERE a.event_id = ? AND a.is_cancelled = 0
+        AND qa.question_id IN {sport_question_ids} AND qa.answer_text = ?
+        AND qb.question_id = ?
         AND a.id NOT IN (
         	SELECT tm.attendee_id FROM team_members tm
             JOIN teams t ON tm.team_id = t.id
-            WHERE t.sport = ?
+            WHERE t.event_id = ? AND t.sport = ?
         )
-        AND qc.question_id = 4 AND qd.question_id = 17
+        AND qc.question_id = ? AND qd.question_id = ?
         ORDER BY school;"
-    )
+    );
+    let members_qry = sqlx::query(&members_stmt)
+    .bind(event.event_id)
     .bind(sport)
+    .bind(event.school_question_id)
+    .bind(event.event_id)
     .bind(sport)
+    .bind(event.phone_question_id)
+    .bind(event.gender_question_id)
     .fetch_all(&mut *db)
     .await
     .ok()?;
@@ -662,7 +1016,7 @@ pub async fn get_no_team(mut db: Connection<Attendize>, secret: &str, sport: &st
             attendee_ref: r.get(7)
         };
         let sports = sqlx::query(
-            "SELECT DISTINCT(answer_text) FROM question_answers WHERE attendee_id = ? AND question_id IN (5, 6, 7, 8)"
+            &format!("SELECT DISTINCT(answer_text) FROM question_answers WHERE attendee_id = ? AND question_id IN {sport_question_ids}")
         )
         .bind(member.attendee_id)
         .fetch_all(&mut *db)
@@ -675,22 +1029,26 @@ pub async fn get_no_team(mut db: Connection<Attendize>, secret: &str, sport: &st
         members.push(member);
     }
     Some(
-        Template::render("no_team_members", context!{members: members, sport: sport})
+        Template::render("no_team_members", context!{members: members, sport: sport, event: event_slug})
     )
 }
 
-#[get("/team/<uuid>?<export>")]
-pub async fn get_team(mut db: Connection<Attendize>, uuid:&str, export:Option<bool>) -> Option<Template> {
+#[get("/e/<event_slug>/team/<uuid>?<export>")]
+pub async fn get_team(mut db: Connection<Attendize>, cfg: LiveConfig, editor: Editor, cookies: &CookieJar<'_>, event_slug: &str, uuid:&str, export:Option<bool>) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
     let row = sqlx::query(
-        "SELECT id, name, sport, gender FROM teams WHERE uuid=?"
+        "SELECT id, name, sport, gender, captain_id, join_code FROM teams WHERE uuid=? AND event_id=? AND deleted_at IS NULL"
     )
     .bind(uuid)
+    .bind(event.event_id)
     .fetch_one(&mut *db).await.ok()?;
 
     let team_id:u32 = row.get(0);
     let name:String = row.get(1);
     let sport:String = row.get(2);
     let gender:String = row.get(3);
+    let captain_id:u32 = row.get(4);
+    let join_code:Option<String> = row.get(5);
 
     let rows = sqlx::query(
         "SELECT attendee_id FROM team_members WHERE team_id=?"
@@ -701,42 +1059,59 @@ pub async fn get_team(mut db: Connection<Attendize>, uuid:&str, export:Option<bo
     let mut members:Vec<CompleteTeamMember> = vec![];
 
     for row in rows {
-        match get_attendee(&mut *db, row.get(0)).await {
-            // Only add valid attendees
-            Ok(ida) => {
-                let full_member = CompleteTeamMember::from_attendee_id(&mut *db, ida.id).await?;
-                members.push(full_member);
-            },
-            Err(_) => ()
+        // Only add valid attendees
+        if let Ok(ida) = get_attendee(&mut db, event, row.get(0)).await {
+            let full_member = CompleteTeamMember::from_attendee_id(&mut db, event, ida.id).await?;
+            members.push(full_member);
         }
     }
-    if export.is_some() {
-        if export.unwrap() {
-            return Some(Template::render("print_team", context!{members: members, name, sport, gender}));
-        }
+    // The printed team is cached as a PDF anyone with the link can download, it has no contact data
+    if export == Some(true) {
+        let members: Vec<CompleteTeamMember> = members.into_iter().map(CompleteTeamMember::without_contact).collect();
+        return Some(Template::render("print_team", context!{members: members, name, sport, gender}));
+    }
+    // Past the roster freeze, captains see the team without the edit forms
+    let roster_frozen = event.roster_frozen(&sport);
+    let can_edit = editor.can_edit(&mut db, event, uuid).await && (roster_frozen.is_none() || editor.is_staff(event));
+    if !can_edit {
+        members = members.into_iter().map(CompleteTeamMember::without_contact).collect();
     }
-    Some(Template::render("view_team", context!{members: members, name, sport, gender, uuid}))
+    let team_rules = team_sport(event, sport.clone(), gender.clone()).ok();
+    let min_players = team_rules.as_ref().map_or(0, |s| s.min_players);
+    let status = match team_rules {
+        Some(s) => team_status(event, &s, members.len(), false),
+        None => TeamStatus::UnknownSport
+    };
+    // Only editors see the join code and the requests, the code lets anyone ask to join
+    let (join_code, requests) = match can_edit {
+        true => (join_code, pending_join_requests(&mut db, team_id).await.unwrap_or_default()),
+        false => (None, vec![])
+    };
+    let csrf_token = csrf_token(cookies);
+    Some(Template::render("view_team", context!{members: members, name, sport, gender, uuid, event: event_slug, can_edit, csrf_token, captain_id, roster_frozen, min_players, status, join_code, requests}))
 }
 
-#[get("/download-team/<uuid>")]
-pub async fn get_download_team(mut db: Connection<Attendize>, uuid: &str) -> Option<NamedFile> {
+#[get("/e/<event_slug>/download-team/<uuid>")]
+pub async fn get_download_team(mut db: Connection<Attendize>, cfg: LiveConfig, event_slug: &str, uuid: &str) -> Option<NamedFile> {
+    let event = cfg.event(event_slug)?;
     let count:i64 = sqlx::query(
-        "SELECT COUNT(*) FROM teams WHERE uuid = ?"
+        "SELECT COUNT(*) FROM teams WHERE uuid = ? AND event_id = ? AND deleted_at IS NULL"
     )
     .bind(uuid)
+    .bind(event.event_id)
     .fetch_one(&mut *db)
     .await.ok()?
     .get(0);
 
     if count == 0 {
-        return None;
+        None
     }
     else {
         let exists:bool = Path::new(format!("ressources/teams/{uuid}.pdf").as_str()).exists();
         if !exists {
             let child = Command::new("wkhtmltopdf")
             .arg("-q")
-            .arg(format!("http://127.0.0.1:8000/view/team/{uuid}?export=true").as_str())
+            .arg(format!("http://127.0.0.1:8000/view/e/{event_slug}/team/{uuid}?export=true").as_str())
             .arg(format!("ressources/teams/{uuid}.pdf").as_str())
             .spawn().ok()?;
             child.output().await.ok()?;
@@ -745,78 +1120,141 @@ pub async fn get_download_team(mut db: Connection<Attendize>, uuid: &str) -> Opt
     }
 }
 
-#[get("/shotgun/<order_ref>?<choice>")]
-pub async fn get_shotgun(mut db: Connection<Attendize>, order_ref: &str, choice: Option<bool>) -> Option<Template> {
-    // Check if the number of Cross Country participants is < 300
-    let nb:i64 = sqlx::query(
-        "SELECT COUNT(*) FROM question_answers qa
-        JOIN attendees a ON qa.attendee_id = a.id
-        WHERE a.event_id = 2 AND qa.question_id = 8 AND qa.answer_text = ?"
-    )
-    .bind("Cross Country")
-    .fetch_one(&mut *db).await.ok()?.get(0);
+/**
+ * Page behind the join link of a team, where teammates ask to join it
+ */
+#[get("/e/<event_slug>/join/<join_code>")]
+pub async fn get_join(mut db: Connection<Attendize>, cfg: LiveConfig, cookies: &CookieJar<'_>, event_slug: &str, join_code: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    let team = joinable_team(&mut db, event, join_code).await?;
+    let csrf_token = csrf_token(cookies);
+    Some(Template::render("join_team", context!{team, join_code, event: event_slug, csrf_token}))
+}
 
-    if nb > 150 {
-        return Some(
-            Template::render("error", context!{message:"We have reached the maximum nummber of participants for Cross Country"})
-        );
-    }
+/**
+ * Address of the Cross Country shotgun sent in the emails, before events had a slug,
+ * the event is the one of the order
+ */
+#[get("/shotgun/<order_ref>")]
+pub async fn get_legacy_shotgun(mut db: Connection<Attendize>, cfg: LiveConfig, order_ref: &str) -> Option<Redirect> {
+    let (order_reference, _) = order_ref.split_once('-')?;
+    let event_id: u32 = sqlx::query("SELECT event_id FROM orders WHERE order_reference = ?")
+        .bind(order_reference)
+        .fetch_optional(&mut *db)
+        .await
+        .ok()??
+        .get(0);
+    let event = cfg.event_by_id(event_id)?;
+    Some(Redirect::to(format!("/e/{}/signup/{order_ref}", event.slug)))
+}
 
-    let id_attendee = retrieve_attendee(&mut *db, order_ref).await.ok()?;
+/**
+ * Former address of the Cross Country shotgun, once events had a slug
+ */
+#[get("/e/<event_slug>/shotgun/<order_ref>")]
+pub fn get_shotgun(event_slug: &str, order_ref: &str) -> Redirect {
+    Redirect::to(format!("/e/{event_slug}/signup/{order_ref}"))
+}
 
-    let athlete_tickets = config::get_option("athlete_ticket_ids");
-    let mut is_an_athlete = false;
-    for ticket_id in athlete_tickets.split(',') {
-        let id = ticket_id.parse::<u32>().unwrap();
-        if id == id_attendee.ticket_id {
-            is_an_athlete = true;
-        }
-    }
-    if !is_an_athlete {
+/**
+ * Sports with a limited number of spots, where athletes sign up first come first served
+ */
+#[get("/e/<event_slug>/signup/<order_ref>")]
+pub async fn get_signups(mut db: Connection<Attendize>, cfg: LiveConfig, cookies: &CookieJar<'_>, event_slug: &str, order_ref: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    let attendee = retrieve_attendee(&mut db, event, order_ref).await.ok()?;
+    if !event.is_athlete_ticket(attendee.ticket_id) {
         return None;
     }
+    let sports = match signups::signup_sports(&mut db, event, attendee.id).await {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Unable to list the sign-ups : {e}");
+            return None;
+        }
+    };
+    let csrf_token = csrf_token(cookies);
+    Some(Template::render("signup", context!{sports, event: event_slug, order_ref, csrf_token}))
+}
 
-    let row = sqlx::query(
-        "SELECT COUNT(*) FROM question_answers WHERE attendee_id = ? AND question_id = 8 AND answer_text = ?"
-    )
-    .bind(id_attendee.id)
-    .bind("Cross Country")
-    .fetch_one(&mut *db).await.ok()?;
+#[post("/e/<event_slug>/signup/<sport>/<order_ref>")]
+pub async fn post_signup(mut db: Connection<Attendize>, cfg: LiveConfig, _csrf: CsrfProtected, event_slug: &str, sport: &str, order_ref: &str) -> Option<Json<SimpleResponse>> {
+    let event = cfg.event(event_slug)?;
+    let attendee = match retrieve_attendee(&mut db, event, order_ref).await {
+        Ok(a) if event.is_athlete_ticket(a.ticket_id) => a,
+        _ => return Some(Json(SimpleResponse {
+            message: String::from("Only athletes can sign up"),
+            code: SimpleResponseCode::UserError
+        }))
+    };
+    let actor = Actor::Attendee(String::from(order_ref));
+    let response = match signups::claim_spot(&mut db, event, sport, attendee.id, &actor).await {
+        Ok(signups::SignupOutcome::Confirmed) => SimpleResponse {
+            message: format!("Your registration to {sport} has been taken into account"),
+            code: SimpleResponseCode::Ok
+        },
+        Ok(signups::SignupOutcome::Waiting(position)) => SimpleResponse {
+            message: format!("{sport} is full, you are number {position} on the waiting list"),
+            code: SimpleResponseCode::Ok
+        },
+        Err(e) => signup_error_response(e, sport)
+    };
+    Some(Json(response))
+}
 
-    let count:i64 = row.get(0);
-    if count > 0 {
-        return None;
+/**
+ * Gives up a spot or a place in the waiting list, the next athlete in line gets the spot
+ */
+#[delete("/e/<event_slug>/signup/<sport>/<order_ref>", format="json", data="<withdrawal>")]
+pub async fn delete_signup(mut db: Connection<Attendize>, cfg: LiveConfig, _csrf: CsrfProtected, event_slug: &str, sport: &str, order_ref: &str, withdrawal: Json<SignupWithdrawal>) -> Option<Json<SimpleResponse>> {
+    let event = cfg.event(event_slug)?;
+    let attendee = match retrieve_attendee(&mut db, event, order_ref).await {
+        Ok(a) => a,
+        Err(_) => return Some(Json(SimpleResponse {
+            message: String::from("Attendee not found"),
+            code: SimpleResponseCode::UserError
+        }))
+    };
+    let holder = sqlx::query("SELECT id FROM attendees WHERE id = ? AND private_reference_number = ?")
+        .bind(attendee.id)
+        .bind(withdrawal.ticket_number.trim())
+        .fetch_optional(&mut *db)
+        .await;
+    match holder {
+        Ok(Some(_)) => (),
+        Ok(None) => return Some(Json(SimpleResponse {
+            message: String::from("This ticket number is not the one of the attendee"),
+            code: SimpleResponseCode::UserError
+        })),
+        Err(e) => return Some(Json(SimpleResponse {
+            message: format!("DB error : {e}"),
+            code: SimpleResponseCode::ServerError
+        }))
     }
+    let actor = Actor::Attendee(String::from(order_ref));
+    let response = match signups::withdraw(&mut db, event, sport, attendee.id, &actor).await {
+        Ok(()) => SimpleResponse {
+            message: format!("You are not signed up for {sport} anymore"),
+            code: SimpleResponseCode::Ok
+        },
+        Err(e) => signup_error_response(e, sport)
+    };
+    Some(Json(response))
+}
 
-    match choice {
-        Some(v) => {
-            if v {
-                let res = sqlx::query(
-                    "INSERT INTO question_answers(attendee_id, event_id, question_id, account_id, answer_text)
-                    VALUES (?, 2, 8, 1, ?)"
-                )
-                .bind(id_attendee.id)
-                .bind("Cross Country")
-                .execute(&mut *db).await;
-                
-                if res.is_err() {
-                    panic!("MYSQL insert error during shotgun");
-                }
+fn signup_error_response(e: signups::SignupError, sport: &str) -> SimpleResponse {
+    let code = match e {
+        signups::SignupError::Database(_) => SimpleResponseCode::ServerError,
+        _ => SimpleResponseCode::UserError
+    };
+    SimpleResponse { message: e.message(sport), code }
+}
 
-                Some(
-                    Template::render("success", context!{message: "Your registration to Cross Country has been taken into account"})
-                )
-            }
-            else {
-                None
-            }
-        }
-        None => {
-            Some(
-                Template::render("shotgun", context!{})
-            )
-        }
-    }
+#[get("/staff/login?<next>")]
+pub async fn get_staff_login(cookies: &CookieJar<'_>, next: Option<String>) -> Template {
+    // Only come back to a page of this site
+    let next = next.filter(|n| n.starts_with('/') && !n.starts_with("//")).unwrap_or_else(|| String::from("/"));
+    Template::render("staff_login", context!{next, csrf_token: csrf_token(cookies)})
 }
 
 #[get("/deposit/success")]
@@ -827,33 +1265,36 @@ pub async fn get_deposit_success() -> Template {
 /**
  * Routes for scan app
  */
-#[get("/check-in/<uuid>")]
-pub async fn get_check_in(uuid: &str) -> Option<Template> {
-    let cfg_secret = get_option("check_in_secret");
-    if cfg_secret.as_str() != uuid {
+#[get("/e/<event_slug>/check-in")]
+pub async fn get_check_in(cfg: LiveConfig, staff: CheckInStaff, cookies: &CookieJar<'_>, event_slug: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.account.can_access(event) {
         return None;
     }
-    let read_only:bool = get_option("check_in_read_only").parse().unwrap();
-    Some(Template::render("scan_ui", context!{uuid: uuid, read_only: read_only}))
+    let read_only = event.check_in_read_only || !staff.can_check_in();
+    let csrf_token = csrf_token(cookies);
+    Some(Template::render("scan_ui", context!{read_only: read_only, event: event_slug, csrf_token}))
 }
 
-#[get("/check-in/mark/<uuid>/<reference>")]
-pub async fn get_mark(mut db: Connection<Attendize>, uuid: &str, reference: &str) -> Option<Json<CheckAttendeeResponse>> {
+#[post("/e/<event_slug>/check-in/mark/<reference>")]
+pub async fn post_mark(mut db: Connection<Attendize>, cfg: LiveConfig, staff: CheckInStaff, feed: &State<dashboard::ArrivalFeed>, _csrf: CsrfProtected, event_slug: &str, reference: &str) -> Option<Json<CheckAttendeeResponse>> {
+    let event = cfg.event(event_slug)?;
     let mut response = CheckAttendeeResponse {
         message: String::from("Unknown error"),
         member: None,
-        ticket_title: String::from("")
+        ticket_title: String::from(""),
+        entitlements: None
     };
-    let cfg_secret = get_option("check_in_secret");
-    if cfg_secret.as_str() != uuid {
+    if !staff.account.can_access(event) {
         return None;
     }
 
     let cr = sqlx::query(
-        "SELECT a.id, a.is_cancelled, a.has_arrived, a.arrival_time, t.title FROM attendees a
+        "SELECT a.id, a.is_cancelled, a.has_arrived, a.arrival_time, t.title, a.ticket_id FROM attendees a
         JOIN tickets t ON a.ticket_id = t.id
-        WHERE a.event_id = 2 AND a.private_reference_number = ?"
+        WHERE a.event_id = ? AND a.private_reference_number = ?"
     )
+    .bind(event.event_id)
     .bind(reference)
     .fetch_one(&mut *db).await.ok()?;
 
@@ -862,8 +1303,9 @@ pub async fn get_mark(mut db: Connection<Attendize>, uuid: &str, reference: &str
     let has_arrived:bool = cr.get(2);
     let arrival_time:Option<NaiveDateTime> = cr.get(3);
     let ticket_title:String = cr.get(4);
+    let entitlements = event.ticket_entitlements(cr.get(5)).cloned();
 
-    let member = CompleteTeamMember::from_attendee_id(&mut *db, attendee_id).await?;
+    let member = CompleteTeamMember::from_attendee_id(&mut db, event, attendee_id).await?;
 
     if is_cancelled {
         response.message = format!("{} {} has cancelled their ticket!", member.first_name, member.last_name);
@@ -873,48 +1315,357 @@ pub async fn get_mark(mut db: Connection<Attendize>, uuid: &str, reference: &str
         response.message = format!("{} {} ticket has already been scanned at {}", member.first_name, member.last_name, arrival_time.unwrap());
         response.member = Some(member);
         response.ticket_title = ticket_title;
+        response.entitlements = entitlements;
         return Some(Json(response));
     }
 
-    let read_only:bool = get_option("check_in_read_only").parse().unwrap();
-
-    if !read_only {
-        sqlx::query(
-            "UPDATE attendees SET has_arrived = 1, arrival_time = NOW() WHERE id=?"
+    if !event.check_in_read_only && staff.can_check_in() {
+        let mut tx = db.begin().await.ok()?;
+        // Another device may have scanned the ticket since it was read, only the first scan checks it in
+        let updated = sqlx::query(
+            "UPDATE attendees SET has_arrived = 1, arrival_time = NOW() WHERE id=? AND has_arrived = 0"
         )
         .bind(attendee_id)
-        .execute(&mut *db)
-        .await.ok()?;
+        .execute(&mut *tx)
+        .await.ok()?
+        .rows_affected();
+        if updated == 0 {
+            let _ = tx.rollback().await;
+            response.message = format!("{} {} ticket has already been scanned", member.first_name, member.last_name);
+            response.member = Some(member);
+            response.ticket_title = ticket_title;
+            response.entitlements = entitlements;
+            return Some(Json(response));
+        }
+
+        let change = audit::Change {
+            attendee_id: Some(attendee_id),
+            before: Some(String::from("not arrived")),
+            after: Some(String::from("arrived")),
+            ..Default::default()
+        };
+        audit::record(&mut tx, event, &staff.actor(), "check_in", change).await.ok()?;
+        tx.commit().await.ok()?;
+        // Only the scan that checked the ticket in gets here, a concurrent one stopped at the update
+        feed.announce(event, format!("{} {}", member.first_name, member.last_name), true);
     }
 
     response.message = String::from("Ok");
     response.member = Some(member);
     response.ticket_title = ticket_title;
+    response.entitlements = entitlements;
+
+    Some(Json(response))
+}
+
+/**
+ * Puts a ticket scanned by mistake back to not arrived, with the reason of the correction
+ */
+#[post("/e/<event_slug>/check-in/undo/<reference>", format="json", data="<undo>")]
+pub async fn post_undo_check_in(mut db: Connection<Attendize>, cfg: LiveConfig, feed: &State<dashboard::ArrivalFeed>, change: ScanningStaffChange, event_slug: &str, reference: &str, undo: Json<CheckInUndo>) -> Option<Json<SimpleResponse>> {
+    let event = cfg.event(event_slug)?;
+    let staff = change.0;
+    if !staff.account.can_access(event) {
+        return None;
+    }
+    let response = match checkin::undo_check_in(&mut db, event, reference, &undo.reason, &staff.actor()).await {
+        Ok(undone) => {
+            let message = format!("Check-in of {} undone, it was scanned by {}", undone.name, undone.scanned_by.as_deref().unwrap_or("an unknown agent"));
+            feed.announce(event, undone.name, false);
+            SimpleResponse { message, code: SimpleResponseCode::Ok }
+        }
+        Err(e) => SimpleResponse {
+            message: e.message(),
+            code: match e {
+                checkin::UndoCheckInError::Database(_) => SimpleResponseCode::ServerError,
+                _ => SimpleResponseCode::UserError
+            }
+        }
+    };
+    Some(Json(response))
+}
+
+/**
+ * Attendees of the event with their entitlements, for a device that checks tickets in without network
+ */
+#[get("/e/<event_slug>/check-in/snapshot")]
+pub async fn get_check_in_snapshot(mut db: Connection<Attendize>, cfg: LiveConfig, key: &State<checkin::SnapshotKey>, staff: ScanningStaff, event_slug: &str) -> Result<Json<checkin::CheckInSnapshot>, Status> {
+    let event = cfg.event(event_slug).ok_or(Status::NotFound)?;
+    if !staff.account.can_access(event) {
+        return Err(Status::NotFound);
+    }
+    // The snapshot is bound to the device, which is recorded with the scans
+    let device = staff.device.as_deref().ok_or(Status::BadRequest)?;
+    match checkin::snapshot(&mut db, event, key, device).await {
+        Ok(snapshot) => {
+            info!("Check-in snapshot of {} sent to {}@{device}", event.slug, staff.account.username);
+            Ok(Json(snapshot))
+        }
+        Err(e) => {
+            error!("Unable to build the check-in snapshot : {e}");
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/**
+ * Checks in the scans made offline by a device, conflicts are reported scan by scan
+ */
+#[post("/e/<event_slug>/check-in/sync", format="json", data="<batch>")]
+pub async fn post_check_in_sync(mut db: Connection<Attendize>, cfg: LiveConfig, key: &State<checkin::SnapshotKey>, feed: &State<dashboard::ArrivalFeed>, change: ScanningStaffChange, event_slug: &str, batch: Json<checkin::SyncBatch>) -> Option<Json<checkin::SyncReport>> {
+    let event = cfg.event(event_slug)?;
+    let staff = change.0;
+    if !staff.account.can_access(event) {
+        return None;
+    }
+    let report = match checkin::sync(&mut db, event, key, &batch, &staff.account.username, staff.device.as_deref()).await {
+        Ok(results) => {
+            let conflicts = results.iter().filter(|r| r.status.is_conflict()).count();
+            let checked_in = results.iter().filter(|r| matches!(r.status, checkin::SyncStatus::CheckedIn)).count();
+            // An earlier scan only moves the arrival time of someone already announced
+            for result in &results {
+                if let (checkin::SyncStatus::CheckedIn, Some(name)) = (result.status, &result.name) {
+                    feed.announce(event, name.clone(), true);
+                }
+            }
+            checkin::SyncReport {
+                message: format!("{checked_in} checked in, {conflicts} conflicts"),
+                code: SimpleResponseCode::Ok,
+                checked_in,
+                conflicts,
+                results
+            }
+        }
+        Err(e) => checkin::SyncReport {
+            message: e.message(),
+            code: match e {
+                checkin::SyncError::Database(_) => SimpleResponseCode::ServerError,
+                _ => SimpleResponseCode::UserError
+            },
+            checked_in: 0,
+            conflicts: 0,
+            results: vec![]
+        }
+    };
+    Some(Json(report))
+}
+
+/**
+ * Arrivals against expected attendees by school, ticket, sport and hour, with
+ * the attendees of each delegation who have not arrived yet
+ */
+#[get("/e/<event_slug>/check-in/stats")]
+pub async fn get_check_in_stats(mut db: Connection<Attendize>, cfg: LiveConfig, staff: SportStaff, event_slug: &str) -> Option<Json<dashboard::ArrivalStats>> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
+        return None;
+    }
+    match dashboard::arrival_stats(&mut db, event).await {
+        Ok(stats) => Some(Json(stats)),
+        Err(e) => {
+            error!("Unable to count the arrivals : {e}");
+            None
+        }
+    }
+}
+
+/**
+ * Server-Sent Events announcing every check-in of the event, the dashboard
+ * reloads the statistics when it receives one
+ */
+#[get("/e/<event_slug>/check-in/live")]
+pub async fn get_check_in_live(cfg: LiveConfig, staff: SportStaff, feed: &State<dashboard::ArrivalFeed>, mut end: Shutdown, event_slug: &str) -> Option<EventStream![]> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
+        return None;
+    }
+    let slug = event.slug.clone();
+    let mut arrivals = feed.subscribe();
+    Some(EventStream! {
+        loop {
+            let arrival = select! {
+                arrival = arrivals.recv() => match arrival {
+                    Ok(arrival) => arrival,
+                    Err(RecvError::Closed) => break,
+                    // Some arrivals were missed, the dashboard reloads everything anyway
+                    Err(RecvError::Lagged(_)) => {
+                        yield Event::data("").event("refresh");
+                        continue;
+                    }
+                },
+                _ = &mut end => break
+            };
+            if arrival.event == slug {
+                yield Event::json(&arrival).event("arrival");
+            }
+        }
+    })
+}
+
+/**
+ * Live arrival statistics for the organizers
+ */
+#[get("/e/<event_slug>/check-in/dashboard")]
+pub async fn get_check_in_dashboard(cfg: LiveConfig, staff: SportStaff, event_slug: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
+        return None;
+    }
+    Some(Template::render("check_in_dashboard", context!{event: event_slug, event_name: &event.name}))
+}
+
+/**
+ * Scan app of the food stands, the service being served is picked on the page
+ */
+#[get("/e/<event_slug>/meals")]
+pub async fn get_meal_stand(cfg: LiveConfig, staff: CheckInStaff, cookies: &CookieJar<'_>, event_slug: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.account.can_access(event) {
+        return None;
+    }
+    let read_only = !staff.can_check_in();
+    let services = &event.meal_services;
+    let csrf_token = csrf_token(cookies);
+    Some(Template::render("meal_stand", context!{services, read_only, event: event_slug, csrf_token}))
+}
 
+/**
+ * Serves a meal of the service to the holder of the scanned ticket, refused when
+ * they were already served at this service or have no ticket of its kind left
+ */
+#[post("/e/<event_slug>/meals/<service>/<reference>")]
+pub async fn post_serve_meal(mut db: Connection<Attendize>, cfg: LiveConfig, change: ScanningStaffChange, event_slug: &str, service: &str, reference: &str) -> Option<Json<meals::MealResponse>> {
+    let event = cfg.event(event_slug)?;
+    let staff = change.0;
+    if !staff.account.can_access(event) {
+        return None;
+    }
+    let response = match meals::serve(&mut db, event, service, reference, &staff.actor()).await {
+        Ok(served) => meals::MealResponse {
+            message: format!("{} can be served {service}", served.name),
+            code: SimpleResponseCode::Ok,
+            name: Some(served.name),
+            balance: Some(served.balance)
+        },
+        Err(e) => {
+            let code = match e {
+                meals::MealError::Database(_) => SimpleResponseCode::ServerError,
+                _ => SimpleResponseCode::UserError
+            };
+            let name = match &e {
+                meals::MealError::Cancelled(name) | meals::MealError::NoEntitlements(name) |
+                meals::MealError::AlreadyServed(name, _) | meals::MealError::NoneLeft(name, _) => Some(name.clone()),
+                _ => None
+            };
+            meals::MealResponse { message: e.message(service), code, name, balance: None }
+        }
+    };
     Some(Json(response))
 }
 
+/**
+ * Meals served at each service, for the invoice of the caterer
+ */
+#[get("/e/<event_slug>/meals")]
+pub async fn get_meal_report(mut db: Connection<Attendize>, cfg: LiveConfig, staff: SportStaff, event_slug: &str) -> Option<Template> {
+    let event = cfg.event(event_slug)?;
+    if !staff.0.can_access(event) {
+        return None;
+    }
+    let reports = match meals::service_reports(&mut db, event).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Unable to count the meals served : {e}");
+            return None;
+        }
+    };
+    let mut totals: Vec<(&str, i64)> = vec![];
+    for kind in [config::MealKind::Breakfast, config::MealKind::Meal, config::MealKind::Dinner] {
+        let served = reports.iter().filter(|r| r.kind == kind).map(|r| r.served).sum();
+        totals.push((kind.name(), served));
+    }
+    Some(Template::render("meal_report", context!{reports, totals, event: event_slug}))
+}
+
 #[launch]
 fn rocket() -> rocket::Rocket<rocket::Build> {
+    // Prints the hash of a password read on stdin, to create the first staff account by hand
+    if std::env::args().nth(1).as_deref() == Some("hash-password") {
+        hash_password_command();
+    }
+    // Refuse to start with an invalid configuration, listing every error found
+    let config = match AppConfig::load() {
+        Ok(c) => c,
+        Err(errors) => {
+            eprintln!("Invalid configuration file '{}' :", config::config_path());
+            for e in errors {
+                eprintln!("  - {e}");
+            }
+            std::process::exit(1);
+        }
+    };
     rocket::build()
+        .manage(SharedConfig::new(config))
+        .manage(dashboard::ArrivalFeed::default())
         .attach(Attendize::init())
+        .attach(AdHoc::on_liftoff("Configuration watcher", |rocket| Box::pin(async move {
+            if let Some(config) = rocket.state::<SharedConfig>() {
+                rocket::tokio::spawn(config::watch(config.clone()));
+                if let Some(db) = Attendize::fetch(rocket) {
+                    rocket::tokio::spawn(signups::promote_on_reload(config.subscribe(), db.0.clone()));
+                }
+            }
+        })))
+        .attach(AdHoc::on_ignite("Check-in snapshot key", |rocket| Box::pin(async move {
+            let key = checkin::SnapshotKey::from_figment(rocket.figment());
+            rocket.manage(key)
+        })))
         .attach(Template::fairing())
         .mount("/api/", routes![ 
             get_check_attendee, 
             get_attendee_sports, 
             post_create_team, 
+            post_validate_team,
+            post_delegation_team,
             get_can_register,
-            get_add_team_member,
-            get_del_team_member,
-            get_mark
+            post_team_member,
+            delete_team_member,
+            put_team_name,
+            put_team_captain,
+            delete_team_route,
+            post_restore_team,
+            post_captain_login,
+            post_join_request,
+            post_accept_join_request,
+            post_decline_join_request,
+            post_logout,
+            post_mark,
+            post_undo_check_in,
+            get_check_in_snapshot,
+            post_check_in_sync,
+            get_check_in_stats,
+            get_check_in_live,
+            post_serve_meal,
+            post_signup,
+            delete_signup,
+            post_staff_login,
+            post_create_staff_account,
+            post_disable_staff_account,
+            post_enable_staff_account,
+            post_reload_config
         ])
         .mount("/", routes![
             get_index, 
             get_ressource, 
             get_welcome,
+            get_legacy_shotgun,
             get_shotgun,
+            get_signups,
+            get_join,
             get_deposit_success,
-            get_check_in
+            get_staff_login,
+            get_check_in,
+            get_meal_stand
         ])
         .mount("/team", routes![
             get_compose,
@@ -923,10 +1674,17 @@ fn rocket() -> rocket::Rocket<rocket::Build> {
         ])
         .mount("/view", routes![
             get_list_teams,
+            get_deleted_teams,
+            get_audit_log,
+            get_signup_lists,
+            get_meal_report,
+            get_check_in_dashboard,
+            get_delegation,
             get_team,
             get_no_team_list,
             get_no_team,
             get_download_team
         ])
-        .register("/api", catchers![not_found, internal_error])
+        .register("/api", catchers![not_found, unauthorized, forbidden, internal_error])
+        .register("/", catchers![staff_login_redirect])
 }
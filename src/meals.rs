@@ -0,0 +1,284 @@
+//! Food tickets spent at the food stands : each attendee gets a balance from the
+//! entitlements of their ticket, and every meal served takes one ticket of its kind
+
+use rocket::serde::Serialize;
+
+use rocket_db_pools::sqlx;
+use rocket_db_pools::sqlx::Row;
+use rocket_db_pools::sqlx::mysql::MySqlConnection;
+
+use crate::config::{EventConfig, MealKind, MealService};
+use crate::audit::{self, Actor, Change};
+use crate::defs::SimpleResponseCode;
+use crate::teams::{in_transaction, is_duplicate_entry};
+
+/**
+ * Reasons why a meal was refused
+ */
+pub enum MealError {
+    /**
+     * Unknown service or service not served right now, with the reason
+     */
+    Closed(String),
+    UnknownTicket,
+    /**
+     * Name of the attendee
+     */
+    Cancelled(String),
+    NoEntitlements(String),
+    /**
+     * Name of the attendee and when they were served, None when it happened during this scan
+     */
+    AlreadyServed(String, Option<String>),
+    NoneLeft(String, MealKind),
+    Database(String)
+}
+
+impl MealError {
+    pub fn message(&self, service: &str) -> String {
+        match self {
+            MealError::Closed(reason) => reason.clone(),
+            MealError::UnknownTicket => String::from("Ticket not found"),
+            MealError::Cancelled(name) => format!("{name} has cancelled their ticket!"),
+            MealError::NoEntitlements(name) => format!("No food tickets configured for the ticket of {name}"),
+            MealError::AlreadyServed(name, Some(at)) => format!("{name} was already served {service} at {at}"),
+            MealError::AlreadyServed(name, None) => format!("{name} was already served {service}"),
+            MealError::NoneLeft(name, kind) => format!("{name} has no {} ticket left", kind.name()),
+            MealError::Database(e) => format!("DB error, rolling back : {e}")
+        }
+    }
+}
+
+/**
+ * Food tickets left to an attendee
+ */
+#[derive(Serialize, Clone, Copy)]
+#[serde(crate = "rocket::serde")]
+pub struct MealBalance {
+    pub breakfasts: u8,
+    pub meals: u8,
+    pub dinners: u8
+}
+
+impl MealBalance {
+    pub fn left(&self, kind: MealKind) -> u8 {
+        match kind {
+            MealKind::Breakfast => self.breakfasts,
+            MealKind::Meal => self.meals,
+            MealKind::Dinner => self.dinners
+        }
+    }
+}
+
+/**
+ * Answer of the food stand app to a scan
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MealResponse {
+    pub message: String,
+    pub code: SimpleResponseCode,
+    pub name: Option<String>,
+    /**
+     * Tickets left once the meal was served
+     */
+    pub balance: Option<MealBalance>
+}
+
+/**
+ * A meal that was served
+ */
+pub struct Served {
+    pub name: String,
+    pub balance: MealBalance
+}
+
+/**
+ * Meals served at one service, as invoiced by the caterer
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ServiceReport {
+    pub service: String,
+    pub kind: MealKind,
+    pub served: i64,
+    pub first_served: Option<String>,
+    pub last_served: Option<String>
+}
+
+/**
+ * Column of meal_balances holding the tickets of a kind
+ */
+fn balance_column(kind: MealKind) -> &'static str {
+    match kind {
+        MealKind::Breakfast => "breakfasts",
+        MealKind::Meal => "meals",
+        MealKind::Dinner => "dinners"
+    }
+}
+
+/**
+ * Serves a meal of the service to the holder of the ticket, taking one of their
+ * food tickets of its kind, reference is the private_reference_number of the QR code
+ */
+pub async fn serve(db: &mut MySqlConnection, event: &EventConfig, service: &str, reference: &str, actor: &Actor) -> Result<Served, MealError> {
+    if let Some(reason) = event.meal_service_closed(service) {
+        return Err(MealError::Closed(reason));
+    }
+    let service = event.meal_service(service).ok_or_else(|| MealError::Closed(format!("Unknown meal service '{service}'")))?;
+    in_transaction(db, MealError::Database, |tx| Box::pin(insert_redemption(tx, event, service, reference, actor))).await
+}
+
+/**
+ * Meals served at every service of the event, in the order of the configuration file
+ *
+ * Services removed from the configuration are listed last, so that no served meal is missing
+ */
+pub async fn service_reports(db: &mut MySqlConnection, event: &EventConfig) -> Result<Vec<ServiceReport>, sqlx::Error> {
+    let mut served: Vec<ServiceReport> = sqlx::query(
+        "SELECT service, kind, COUNT(*), DATE_FORMAT(MIN(created_at), '%Y-%m-%d %H:%i'), DATE_FORMAT(MAX(created_at), '%Y-%m-%d %H:%i')
+        FROM meal_redemptions WHERE event_id = ?
+        GROUP BY service, kind ORDER BY MIN(created_at)"
+    )
+    .bind(event.event_id)
+    .fetch_all(db)
+    .await?
+    .iter()
+    .filter_map(|row| {
+        let kind: String = row.get(1);
+        Some(ServiceReport {
+            service: row.get(0),
+            kind: kind.parse().ok()?,
+            served: row.get(2),
+            first_served: row.get(3),
+            last_served: row.get(4)
+        })
+    })
+    .collect();
+
+    let mut reports = vec![];
+    for service in &event.meal_services {
+        match served.iter().position(|r| r.service == service.name && r.kind == service.kind) {
+            Some(i) => reports.push(served.remove(i)),
+            None => reports.push(ServiceReport {
+                service: service.name.clone(),
+                kind: service.kind,
+                served: 0,
+                first_served: None,
+                last_served: None
+            })
+        }
+    }
+    reports.append(&mut served);
+    Ok(reports)
+}
+
+/**
+ * Balance of the attendee, locked until commit so that two food stands scanning
+ * the same ticket serve one after the other
+ *
+ * The balance is created from the entitlements of the ticket on the first scan,
+ * later changes of the entitlements do not apply to it
+ */
+async fn lock_balance(tx: &mut MySqlConnection, event: &EventConfig, attendee_id: u32, ticket_id: u32) -> Result<Option<MealBalance>, sqlx::Error> {
+    if let Some(entitlements) = event.ticket_entitlements(ticket_id) {
+        sqlx::query("INSERT IGNORE INTO meal_balances(event_id, attendee_id, breakfasts, meals, dinners) VALUES (?, ?, ?, ?, ?)")
+            .bind(event.event_id)
+            .bind(attendee_id)
+            .bind(entitlements.breakfasts)
+            .bind(entitlements.meals)
+            .bind(entitlements.dinners)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let balance = sqlx::query("SELECT breakfasts, meals, dinners FROM meal_balances WHERE event_id = ? AND attendee_id = ? FOR UPDATE")
+        .bind(event.event_id)
+        .bind(attendee_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .map(|row| MealBalance {
+            breakfasts: row.get(0),
+            meals: row.get(1),
+            dinners: row.get(2)
+        });
+    Ok(balance)
+}
+
+async fn insert_redemption(tx: &mut MySqlConnection, event: &EventConfig, service: &MealService, reference: &str, actor: &Actor) -> Result<Served, MealError> {
+    let db_error = |e: sqlx::Error| MealError::Database(e.to_string());
+
+    let attendee = sqlx::query(
+        "SELECT id, is_cancelled, ticket_id, CONCAT(first_name, ' ', last_name) FROM attendees
+        WHERE event_id = ? AND private_reference_number = ?"
+    )
+    .bind(event.event_id)
+    .bind(reference)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .ok_or(MealError::UnknownTicket)?;
+    let attendee_id: u32 = attendee.get(0);
+    let is_cancelled: bool = attendee.get(1);
+    let name: String = attendee.get(3);
+    if is_cancelled {
+        return Err(MealError::Cancelled(name));
+    }
+
+    let mut balance = lock_balance(&mut *tx, event, attendee_id, attendee.get(2)).await
+        .map_err(db_error)?
+        .ok_or_else(|| MealError::NoEntitlements(name.clone()))?;
+
+    let served_at: Option<String> = sqlx::query(
+        "SELECT DATE_FORMAT(created_at, '%H:%i') FROM meal_redemptions WHERE event_id = ? AND service = ? AND attendee_id = ?"
+    )
+    .bind(event.event_id)
+    .bind(&service.name)
+    .bind(attendee_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .map(|row| row.get(0));
+    if served_at.is_some() {
+        return Err(MealError::AlreadyServed(name, served_at));
+    }
+
+    let left = balance.left(service.kind);
+    if left == 0 {
+        return Err(MealError::NoneLeft(name, service.kind));
+    }
+    let column = balance_column(service.kind);
+    sqlx::query(&format!("UPDATE meal_balances SET {column} = {column} - 1 WHERE event_id = ? AND attendee_id = ?"))
+        .bind(event.event_id)
+        .bind(attendee_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    sqlx::query("INSERT INTO meal_redemptions(event_id, service, kind, attendee_id) VALUES (?, ?, ?, ?)")
+        .bind(event.event_id)
+        .bind(&service.name)
+        .bind(service.kind.name())
+        .bind(attendee_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| if is_duplicate_entry(&e) {
+            MealError::AlreadyServed(name.clone(), None)
+        } else {
+            db_error(e)
+        })?;
+
+    let change = Change {
+        attendee_id: Some(attendee_id),
+        before: Some(format!("{column} : {left}")),
+        after: Some(format!("{column} : {}", left - 1)),
+        details: service.name.clone(),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "serve_meal", change).await.map_err(db_error)?;
+
+    match service.kind {
+        MealKind::Breakfast => balance.breakfasts -= 1,
+        MealKind::Meal => balance.meals -= 1,
+        MealKind::Dinner => balance.dinners -= 1
+    }
+    Ok(Served { name, balance })
+}
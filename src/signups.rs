@@ -0,0 +1,385 @@
+//! Sign-ups to sports with a capacity : athletes claim one of the spots, first come first served,
+//! and wait in line once the sport is full
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use rocket::serde::Serialize;
+use rocket::tokio::sync::watch;
+
+use rocket_db_pools::sqlx;
+use rocket_db_pools::sqlx::{Acquire, Row};
+use rocket_db_pools::sqlx::mysql::{MySqlConnection, MySqlPool};
+
+use crate::config::{AppConfig, EventConfig};
+use crate::audit::{self, Actor, Change};
+use crate::teams::{in_transaction, is_duplicate_entry};
+
+/**
+ * Reasons why a sign-up or a withdrawal was refused
+ */
+pub enum SignupError {
+    /**
+     * The sport has no sign-up or they are not open, with the reason
+     */
+    Closed(String),
+    AlreadySignedUp,
+    NotSignedUp,
+    Database(String)
+}
+
+impl SignupError {
+    pub fn message(&self, sport: &str) -> String {
+        match self {
+            SignupError::Closed(reason) => reason.clone(),
+            SignupError::AlreadySignedUp => format!("You are already signed up for {sport}"),
+            SignupError::NotSignedUp => format!("You are not signed up for {sport}"),
+            SignupError::Database(e) => format!("DB error, rolling back : {e}")
+        }
+    }
+}
+
+/**
+ * Where a sign-up ended up
+ */
+pub enum SignupOutcome {
+    Confirmed,
+    /**
+     * Position in the waiting list, starting at 1
+     */
+    Waiting(i64)
+}
+
+/**
+ * A sport with a capacity, as shown to an athlete
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SignupSport {
+    pub sport: String,
+    pub capacity: u32,
+    pub taken: i64,
+    pub remaining: i64,
+    pub waiting: i64,
+    pub signed_up: bool,
+    /**
+     * Position of the athlete in the waiting list, starting at 1
+     */
+    pub waiting_position: Option<i64>,
+    /**
+     * Why sign-ups are not possible right now, None when they are open
+     */
+    pub closed: Option<String>
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SignupEntry {
+    pub name: String,
+    pub attendee_ref: String,
+    pub created_at: String,
+    /**
+     * When the athlete left the waiting list
+     */
+    pub promoted_at: Option<String>
+}
+
+/**
+ * Athletes of a sport with a capacity, as shown to the staff
+ */
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SignupList {
+    pub sport: String,
+    pub capacity: u32,
+    pub confirmed: Vec<SignupEntry>,
+    /**
+     * In the order in which they will be promoted
+     */
+    pub waiting: Vec<SignupEntry>
+}
+
+/**
+ * Every sport with a capacity of the event, with its remaining spots
+ */
+pub async fn signup_sports(db: &mut MySqlConnection, event: &EventConfig, attendee_id: u32) -> Result<Vec<SignupSport>, sqlx::Error> {
+    let mut counts: HashMap<(String, String), i64> = HashMap::new();
+    for row in sqlx::query("SELECT sport, status, COUNT(*) FROM sport_signups WHERE event_id = ? GROUP BY sport, status")
+        .bind(event.event_id)
+        .fetch_all(&mut *db)
+        .await? {
+        counts.insert((row.get(0), row.get(1)), row.get(2));
+    }
+    // Position of each sign-up of the athlete among the sign-ups of the same status
+    let mut mine: HashMap<String, (String, i64)> = HashMap::new();
+    for row in sqlx::query(
+        "SELECT s.sport, s.status, (SELECT COUNT(*) FROM sport_signups o
+            WHERE o.event_id = s.event_id AND o.sport = s.sport AND o.status = s.status AND o.id <= s.id)
+        FROM sport_signups s WHERE s.event_id = ? AND s.attendee_id = ?"
+    )
+    .bind(event.event_id)
+    .bind(attendee_id)
+    .fetch_all(&mut *db)
+    .await? {
+        mine.insert(row.get(0), (row.get(1), row.get(2)));
+    }
+
+    let mut sports = vec![];
+    for rules in event.signup_sports() {
+        let capacity = rules.capacity.unwrap_or(0);
+        let count = |status: &str| counts.get(&(rules.name.clone(), String::from(status))).copied().unwrap_or(0);
+        let taken = count("confirmed");
+        let status = mine.get(&rules.name);
+        sports.push(SignupSport {
+            sport: rules.name.clone(),
+            capacity,
+            taken,
+            remaining: (i64::from(capacity) - taken).max(0),
+            waiting: count("waiting"),
+            signed_up: status.is_some_and(|(s, _)| s == "confirmed"),
+            waiting_position: status.filter(|(s, _)| s == "waiting").map(|(_, position)| *position),
+            closed: event.signup_closed(&rules.name)
+        });
+    }
+    Ok(sports)
+}
+
+/**
+ * Confirmed athletes and waiting list of every sport with a capacity
+ */
+pub async fn signup_lists(db: &mut MySqlConnection, event: &EventConfig) -> Result<Vec<SignupList>, sqlx::Error> {
+    let mut lists = vec![];
+    for rules in event.signup_sports() {
+        let mut list = SignupList {
+            sport: rules.name.clone(),
+            capacity: rules.capacity.unwrap_or(0),
+            confirmed: vec![],
+            waiting: vec![]
+        };
+        for row in sqlx::query(
+            "SELECT s.status, CONCAT(a.first_name, ' ', a.last_name), CONCAT(o.order_reference, '-', a.reference_index),
+                DATE_FORMAT(s.created_at, '%Y-%m-%d %H:%i:%s'), DATE_FORMAT(s.promoted_at, '%Y-%m-%d %H:%i:%s')
+            FROM sport_signups s
+            JOIN attendees a ON s.attendee_id = a.id
+            JOIN orders o ON a.order_id = o.id
+            WHERE s.event_id = ? AND s.sport = ?
+            ORDER BY s.id"
+        )
+        .bind(event.event_id)
+        .bind(&rules.name)
+        .fetch_all(&mut *db)
+        .await? {
+            let status: String = row.get(0);
+            let entry = SignupEntry {
+                name: row.get(1),
+                attendee_ref: row.get(2),
+                created_at: row.get(3),
+                promoted_at: row.get(4)
+            };
+            match status.as_str() {
+                "waiting" => list.waiting.push(entry),
+                _ => list.confirmed.push(entry)
+            }
+        }
+        lists.push(list);
+    }
+    Ok(lists)
+}
+
+/**
+ * Gives the athlete one of the remaining spots of the sport, or puts them
+ * at the end of its waiting list when the sport is full
+ */
+pub async fn claim_spot(db: &mut MySqlConnection, event: &EventConfig, sport: &str, attendee_id: u32, actor: &Actor) -> Result<SignupOutcome, SignupError> {
+    if let Some(reason) = event.signup_closed(sport) {
+        return Err(SignupError::Closed(reason));
+    }
+    in_transaction(db, SignupError::Database, |tx| Box::pin(insert_signup(tx, event, sport, attendee_id, actor))).await
+}
+
+/**
+ * Removes the sign-up of the athlete, whether confirmed or waiting,
+ * the freed spot goes to the first athlete of the waiting list
+ */
+pub async fn withdraw(db: &mut MySqlConnection, event: &EventConfig, sport: &str, attendee_id: u32, actor: &Actor) -> Result<(), SignupError> {
+    if event.sports.get(sport).and_then(|s| s.capacity).is_none() {
+        return Err(SignupError::Closed(format!("There is no sign-up for {sport}")));
+    }
+    in_transaction(db, SignupError::Database, |tx| Box::pin(delete_signup(tx, event, sport, attendee_id, actor))).await
+}
+
+/**
+ * Fills the free spots of every sport of the event from their waiting lists,
+ * used when the capacity of a sport was raised
+ */
+pub async fn promote_all(db: &mut MySqlConnection, event: &EventConfig) -> Result<(), sqlx::Error> {
+    for rules in event.signup_sports() {
+        let mut tx = db.begin().await?;
+        lock_sport(&mut tx, event, &rules.name).await?;
+        promote_waiting(&mut tx, event, &rules.name, "capacity raised").await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+/**
+ * Promotes waiting athletes every time the configuration is reloaded,
+ * a staff member raising a capacity frees spots without any request
+ */
+pub async fn promote_on_reload(mut reloads: watch::Receiver<Arc<AppConfig>>, pool: MySqlPool) {
+    while reloads.changed().await.is_ok() {
+        let config = reloads.borrow().clone();
+        let mut db = match pool.acquire().await {
+            Ok(db) => db,
+            Err(e) => {
+                error!("Unable to promote waiting athletes : {e}");
+                continue;
+            }
+        };
+        for event in config.events.values() {
+            if let Err(e) = promote_all(&mut db, event).await {
+                error!("Unable to promote waiting athletes of {} : {e}", event.slug);
+            }
+        }
+    }
+}
+
+/**
+ * Locks the row of the sport in sport_signup_locks, sign-ups and withdrawals
+ * of the same sport wait for each other from here until commit
+ */
+async fn lock_sport(tx: &mut MySqlConnection, event: &EventConfig, sport: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT IGNORE INTO sport_signup_locks(event_id, sport) VALUES (?, ?)")
+        .bind(event.event_id)
+        .bind(sport)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("SELECT sport FROM sport_signup_locks WHERE event_id = ? AND sport = ? FOR UPDATE")
+        .bind(event.event_id)
+        .bind(sport)
+        .fetch_one(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+async fn count_status(tx: &mut MySqlConnection, event: &EventConfig, sport: &str, status: &str) -> Result<i64, sqlx::Error> {
+    Ok(sqlx::query("SELECT COUNT(*) FROM sport_signups WHERE event_id = ? AND sport = ? AND status = ?")
+        .bind(event.event_id)
+        .bind(sport)
+        .bind(status)
+        .fetch_one(&mut *tx)
+        .await?
+        .get(0))
+}
+
+async fn insert_signup(tx: &mut MySqlConnection, event: &EventConfig, sport: &str, attendee_id: u32, actor: &Actor) -> Result<SignupOutcome, SignupError> {
+    let db_error = |e: sqlx::Error| SignupError::Database(e.to_string());
+    let capacity = event.sports.get(sport).and_then(|s| s.capacity).unwrap_or(0);
+
+    lock_sport(&mut *tx, event, sport).await.map_err(db_error)?;
+    let taken = count_status(&mut *tx, event, sport, "confirmed").await.map_err(db_error)?;
+    let outcome = match taken < i64::from(capacity) {
+        true => SignupOutcome::Confirmed,
+        false => SignupOutcome::Waiting(count_status(&mut *tx, event, sport, "waiting").await.map_err(db_error)? + 1)
+    };
+    let status = match outcome {
+        SignupOutcome::Confirmed => "confirmed",
+        SignupOutcome::Waiting(_) => "waiting"
+    };
+
+    sqlx::query("INSERT INTO sport_signups(event_id, sport, attendee_id, status) VALUES (?, ?, ?, ?)")
+        .bind(event.event_id)
+        .bind(sport)
+        .bind(attendee_id)
+        .bind(status)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| if is_duplicate_entry(&e) {
+            SignupError::AlreadySignedUp
+        } else {
+            db_error(e)
+        })?;
+
+    let details = match outcome {
+        SignupOutcome::Confirmed => format!("spot {} of {capacity}", taken + 1),
+        SignupOutcome::Waiting(position) => format!("position {position} in the waiting list")
+    };
+    let change = Change {
+        attendee_id: Some(attendee_id),
+        after: Some(format!("{sport} : {status}")),
+        details,
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "signup", change).await.map_err(db_error)?;
+    Ok(outcome)
+}
+
+async fn delete_signup(tx: &mut MySqlConnection, event: &EventConfig, sport: &str, attendee_id: u32, actor: &Actor) -> Result<(), SignupError> {
+    let db_error = |e: sqlx::Error| SignupError::Database(e.to_string());
+
+    lock_sport(&mut *tx, event, sport).await.map_err(db_error)?;
+    let status: String = sqlx::query("SELECT status FROM sport_signups WHERE event_id = ? AND sport = ? AND attendee_id = ?")
+        .bind(event.event_id)
+        .bind(sport)
+        .bind(attendee_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or(SignupError::NotSignedUp)?
+        .get(0);
+
+    sqlx::query("DELETE FROM sport_signups WHERE event_id = ? AND sport = ? AND attendee_id = ?")
+        .bind(event.event_id)
+        .bind(sport)
+        .bind(attendee_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let change = Change {
+        attendee_id: Some(attendee_id),
+        before: Some(format!("{sport} : {status}")),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "withdraw", change).await.map_err(db_error)?;
+
+    promote_waiting(&mut *tx, event, sport, "withdrawal").await.map_err(db_error)?;
+    Ok(())
+}
+
+/**
+ * Confirms the first waiting athletes while the sport has free spots,
+ * the sport must be locked by the caller
+ */
+async fn promote_waiting(tx: &mut MySqlConnection, event: &EventConfig, sport: &str, reason: &str) -> Result<(), sqlx::Error> {
+    let capacity = event.sports.get(sport).and_then(|s| s.capacity).unwrap_or(0);
+    let taken = count_status(&mut *tx, event, sport, "confirmed").await?;
+    let free = i64::from(capacity) - taken;
+    if free <= 0 {
+        return Ok(());
+    }
+
+    let promoted = sqlx::query(
+        "SELECT id, attendee_id FROM sport_signups WHERE event_id = ? AND sport = ? AND status = 'waiting' ORDER BY id LIMIT ?"
+    )
+    .bind(event.event_id)
+    .bind(sport)
+    .bind(free)
+    .fetch_all(&mut *tx)
+    .await?;
+    for row in promoted {
+        let signup_id: u32 = row.get(0);
+        sqlx::query("UPDATE sport_signups SET status = 'confirmed', promoted_at = NOW() WHERE id = ?")
+            .bind(signup_id)
+            .execute(&mut *tx)
+            .await?;
+        let change = Change {
+            attendee_id: Some(row.get(1)),
+            before: Some(format!("{sport} : waiting")),
+            after: Some(format!("{sport} : confirmed")),
+            details: String::from(reason),
+            ..Default::default()
+        };
+        audit::record(&mut *tx, event, &Actor::System, "promote", change).await?;
+    }
+    Ok(())
+}
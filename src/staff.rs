@@ -0,0 +1,276 @@
+//! Staff accounts with their roles, passwords are stored as Argon2 hashes
+
+use std::io::BufRead;
+
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+
+use rocket::serde::{Serialize, Deserialize};
+use rocket::tokio::task;
+
+use rocket_db_pools::sqlx;
+use rocket_db_pools::sqlx::Row;
+use rocket_db_pools::sqlx::mysql::{MySqlConnection, MySqlRow};
+
+use crate::config::EventConfig;
+
+/**
+ * What a staff member is allowed to do
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "rocket::serde")]
+pub enum StaffRole {
+    /**
+     * Sees and edits every team, manages staff accounts
+     */
+    #[serde(rename = "sport_admin")]
+    SportAdmin,
+    /**
+     * Scans tickets at the entrance
+     */
+    #[serde(rename = "checkin_agent")]
+    CheckInAgent,
+    /**
+     * Manages the teams of their school
+     */
+    #[serde(rename = "delegation_leader")]
+    DelegationLeader,
+    /**
+     * Sees team lists and looks up tickets without changing anything
+     */
+    #[serde(rename = "read_only")]
+    ReadOnly
+}
+
+impl StaffRole {
+    /**
+     * Name of the role in the staff_accounts table
+     */
+    pub fn stored_name(&self) -> &'static str {
+        match self {
+            StaffRole::SportAdmin => "sport_admin",
+            StaffRole::CheckInAgent => "checkin_agent",
+            StaffRole::DelegationLeader => "delegation_leader",
+            StaffRole::ReadOnly => "read_only"
+        }
+    }
+
+    pub fn from_stored_name(name: &str) -> Option<StaffRole> {
+        match name {
+            "sport_admin" => Some(StaffRole::SportAdmin),
+            "checkin_agent" => Some(StaffRole::CheckInAgent),
+            "delegation_leader" => Some(StaffRole::DelegationLeader),
+            "read_only" => Some(StaffRole::ReadOnly),
+            _ => None
+        }
+    }
+}
+
+/**
+ * An active staff account
+ */
+#[derive(Clone, Debug)]
+pub struct StaffAccount {
+    pub id: u32,
+    pub username: String,
+    pub role: StaffRole,
+    /**
+     * Attendize event the account is limited to, None for every event
+     */
+    pub event_id: Option<u32>,
+    /**
+     * School of a delegation leader
+     */
+    pub school_id: Option<u32>
+}
+
+/**
+ * Account created by a sport admin
+ */
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewStaffAccount {
+    pub username: String,
+    pub password: String,
+    pub role: StaffRole,
+    /**
+     * Slug of the event the account is limited to, every event when missing
+     */
+    pub event: Option<String>,
+    pub school_id: Option<u32>
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct StaffLogin {
+    pub username: String,
+    pub password: String
+}
+
+impl StaffAccount {
+    fn from_row(row: &MySqlRow) -> Option<StaffAccount> {
+        let role: String = row.get(2);
+        Some(StaffAccount {
+            id: row.get(0),
+            username: row.get(1),
+            role: StaffRole::from_stored_name(&role)?,
+            event_id: row.get(3),
+            school_id: row.get(4)
+        })
+    }
+
+    /**
+     * Account of a session, None once the account has been disabled
+     */
+    pub async fn find_active(db: &mut MySqlConnection, id: u32) -> Option<StaffAccount> {
+        let row = sqlx::query(
+            "SELECT id, username, role, event_id, school_id FROM staff_accounts WHERE id = ? AND disabled_at IS NULL"
+        )
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .ok()??;
+        StaffAccount::from_row(&row)
+    }
+
+    /**
+     * Checks a username and password, disabled accounts cannot log in
+     */
+    pub async fn authenticate(db: &mut MySqlConnection, username: &str, password: &str) -> Option<StaffAccount> {
+        let row = sqlx::query(
+            "SELECT id, username, role, event_id, school_id, password_hash FROM staff_accounts WHERE username = ? AND disabled_at IS NULL"
+        )
+        .bind(username)
+        .fetch_optional(db)
+        .await
+        .ok()??;
+        let password_hash: String = row.get(5);
+        // Argon2 takes tens of milliseconds on purpose, which would hold up a Rocket worker
+        let password = password.to_owned();
+        let verified = task::spawn_blocking(move || verify_password(&password, &password_hash))
+            .await
+            .unwrap_or(false);
+        match verified {
+            true => StaffAccount::from_row(&row),
+            false => None
+        }
+    }
+
+    pub fn can_access(&self, event: &EventConfig) -> bool {
+        self.event_id.is_none_or(|event_id| event_id == event.event_id)
+    }
+
+    pub fn has_role(&self, roles: &[StaffRole]) -> bool {
+        roles.contains(&self.role)
+    }
+}
+
+/**
+ * Stores a new account, the password is only kept hashed
+ */
+pub async fn create_account(db: &mut MySqlConnection, account: &NewStaffAccount, event_id: Option<u32>) -> Result<u64, String> {
+    if account.role == StaffRole::DelegationLeader && account.school_id.is_none() {
+        return Err(String::from("A delegation leader needs a school_id"));
+    }
+    if account.password.len() < 10 {
+        return Err(String::from("The password should be at least 10 characters long"));
+    }
+    let password = account.password.clone();
+    let password_hash = task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|e| format!("Unable to hash password : {e}"))??;
+    let res = sqlx::query(
+        "INSERT INTO staff_accounts(username, password_hash, role, event_id, school_id) VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(&account.username)
+    .bind(password_hash)
+    .bind(account.role.stored_name())
+    .bind(event_id)
+    .bind(account.school_id)
+    .execute(db)
+    .await;
+
+    match res {
+        Ok(r) => Ok(r.last_insert_id()),
+        Err(e) if crate::teams::is_duplicate_entry(&e) => Err(format!("The username '{}' is already taken", account.username)),
+        Err(e) => Err(format!("DB error : {e}"))
+    }
+}
+
+/**
+ * Disables or re-enables an account, returns false when there is no such account
+ */
+pub async fn set_account_disabled(db: &mut MySqlConnection, id: u32, disabled: bool) -> Result<bool, String> {
+    let stmt = match disabled {
+        true => "UPDATE staff_accounts SET disabled_at = NOW() WHERE id = ? AND disabled_at IS NULL",
+        false => "UPDATE staff_accounts SET disabled_at = NULL WHERE id = ?"
+    };
+    let res = sqlx::query(stmt)
+        .bind(id)
+        .execute(&mut *db)
+        .await
+        .map_err(|e| format!("DB error : {e}"))?;
+    if res.rows_affected() > 0 {
+        return Ok(true);
+    }
+    // Already in the requested state
+    let exists = sqlx::query("SELECT id FROM staff_accounts WHERE id = ?")
+        .bind(id)
+        .fetch_optional(&mut *db)
+        .await
+        .map_err(|e| format!("DB error : {e}"))?;
+    Ok(exists.is_some())
+}
+
+/**
+ * Event an account is limited to
+ */
+pub async fn account_event_id(db: &mut MySqlConnection, id: u32) -> Option<Option<u32>> {
+    let row = sqlx::query("SELECT event_id FROM staff_accounts WHERE id = ?")
+        .bind(id)
+        .fetch_optional(db)
+        .await
+        .ok()??;
+    Some(row.get(0))
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| format!("Unable to hash password : {e}"))
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(e) => {
+            error!("Invalid password hash in staff_accounts : {e}");
+            false
+        }
+    }
+}
+
+/**
+ * `pegalpha hash-password` : reads a password on stdin and prints its hash,
+ * used to insert the first sport admin account by hand
+ */
+pub fn hash_password_command() -> ! {
+    let mut password = String::new();
+    if std::io::stdin().lock().read_line(&mut password).is_err() {
+        eprintln!("Unable to read the password from stdin");
+        std::process::exit(1);
+    }
+    match hash_password(password.trim_end_matches(['\r', '\n'])) {
+        Ok(h) => {
+            println!("{h}");
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,1121 @@
+use rand::Rng;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::Serialize;
+use rocket::futures::future::BoxFuture;
+
+use rocket_db_pools::sqlx;
+use rocket_db_pools::sqlx::{Acquire, Row, Transaction};
+use rocket_db_pools::sqlx::mysql::{MySql, MySqlConnection, MySqlDatabaseError};
+
+use crate::defs::*;
+use crate::config::{EventConfig, DATE_FORMAT};
+use crate::checks::{attendee_name, get_attendee, school_quota_message, status_message, validate_attendee};
+use crate::audit::{self, Actor, Change};
+
+/**
+ * Reasons why a team that passed validation could still not be stored
+ */
+#[derive(Debug)]
+pub enum CreateTeamError {
+    /**
+     * Another team of the same school was registered in the meantime
+     */
+    SchoolQuotaReached,
+    /**
+     * This attendee joined another team of the same sport in the meantime
+     */
+    AlreadyInATeam(u32),
+    Database(String)
+}
+
+/**
+ * MySQL error number of a unique key violation
+ */
+const ER_DUP_ENTRY: u16 = 1062;
+
+/**
+ * Whether this error comes from a unique key, for team members this is
+ * the one team per sport per attendee rule
+ */
+pub fn is_duplicate_entry(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Database(db_err) => db_err
+            .try_downcast_ref::<MySqlDatabaseError>()
+            .is_some_and(|mysql_err| mysql_err.number() == ER_DUP_ENTRY),
+        _ => false
+    }
+}
+
+/**
+ * Runs the changes of a public function in a transaction, committed when they
+ * succeed and rolled back otherwise
+ *
+ * database wraps the errors of the transaction itself, such as a failed commit
+ */
+pub async fn in_transaction<'a, T, E, F>(db: &'a mut MySqlConnection, database: fn(String) -> E, changes: F) -> Result<T, E>
+where
+    F: for<'c> FnOnce(&'c mut Transaction<'a, MySql>) -> BoxFuture<'c, Result<T, E>>
+{
+    let mut tx = db.begin().await.map_err(|e| database(e.to_string()))?;
+    match changes(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await.map_err(|e| database(format!("DB commit error : {e}")))?;
+            Ok(value)
+        }
+        Err(e) => {
+            // Dropping the transaction rolls it back anyway
+            let _ = tx.rollback().await;
+            Err(e)
+        }
+    }
+}
+
+/**
+ * Stores a validated team and its members, the first attendee being the captain
+ *
+ * Returns the id of the new team
+ */
+pub async fn create_team(db: &mut MySqlConnection, event: &EventConfig, team: &Team, sport: &Sport, attendees: &[IdentifiedAttendee], actor: &Actor) -> Result<u64, CreateTeamError> {
+    // Let this be a transaction, because of multiple INSERT statements
+    in_transaction(db, CreateTeamError::Database, |tx| Box::pin(insert_team(tx, event, team, sport, attendees, actor))).await
+}
+
+async fn insert_team(tx: &mut MySqlConnection, event: &EventConfig, team: &Team, sport: &Sport, attendees: &[IdentifiedAttendee], actor: &Actor) -> Result<u64, CreateTeamError> {
+    let db_error = |e: sqlx::Error| CreateTeamError::Database(e.to_string());
+    let captain = attendees.first().ok_or_else(|| CreateTeamError::Database(String::from("Team has no captain")))?;
+
+    // Registrations of the same school wait for each other from here until commit,
+    // the count below then sees every team committed before
+    sqlx::query("SELECT id FROM question_options WHERE id = ? FOR UPDATE")
+        .bind(captain.school_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    let school_nb_teams: i64 = sqlx::query(
+        "SELECT COUNT(*) FROM teams WHERE event_id = ? AND school_id = ? AND sport = ? AND deleted_at IS NULL"
+    )
+    .bind(event.event_id)
+    .bind(captain.school_id)
+    .bind(&sport.name)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .get(0);
+
+    if school_nb_teams >= i64::from(sport.max_teams_per_school) {
+        return Err(CreateTeamError::SchoolQuotaReached);
+    }
+
+    let team_id = sqlx::query("INSERT INTO teams(event_id, school_id, name, captain_id, uuid, join_code, sport, gender) VALUES (?,?,?,?,UUID(),?,?,?)")
+        .bind(event.event_id)
+        .bind(captain.school_id)
+        .bind(&team.name)
+        .bind(captain.id)
+        .bind(generate_join_code())
+        .bind(&sport.name)
+        .bind(team.gender.stored_name())
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .last_insert_id();
+
+    // Add team members, the unique key on (attendee_id, event_id, sport) rejects
+    // anyone who joined another team of this sport since validation
+    for participant in attendees {
+        sqlx::query("INSERT INTO team_members(team_id, event_id, sport, attendee_id) VALUES (?, ?, ?, ?)")
+            .bind(team_id)
+            .bind(event.event_id)
+            .bind(&sport.name)
+            .bind(participant.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| if is_duplicate_entry(&e) {
+                CreateTeamError::AlreadyInATeam(participant.id)
+            } else {
+                db_error(e)
+            })?;
+    }
+
+    let change = Change {
+        team_id: u32::try_from(team_id).ok(),
+        after: Some(format!("'{}', members : {}", team.name, team.refs.join(", "))),
+        details: format!("{} {}", sport.name, team.gender.stored_name()),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "create", change).await.map_err(db_error)?;
+    Ok(team_id)
+}
+
+/**
+ * Reasons why a roster edit was refused
+ */
+pub enum EditTeamError {
+    TeamNotFound,
+    /**
+     * The join request does not exist or was already answered
+     */
+    RequestNotFound,
+    /**
+     * The team would not follow the rules of its sport anymore
+     */
+    Invalid(TeamValidationReport),
+    Database(String)
+}
+
+/**
+ * A team being edited, its row stays locked until the edit is committed
+ */
+struct LockedTeam {
+    id: u32,
+    name: String,
+    school_id: u32,
+    captain_id: u32,
+    sport: Sport,
+    nb_members: usize
+}
+
+/**
+ * Adds an attendee to an existing team
+ *
+ * The attendee is validated against the team's sport and stored gender, and the
+ * team must stay within the size and school rules of its sport
+ */
+pub async fn add_team_member(db: &mut MySqlConnection, event: &EventConfig, uuid: &str, attendee: &IdentifiedAttendee, reference: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(insert_team_member(tx, event, uuid, attendee, reference, actor))).await?;
+    invalidate_team_pdf(uuid);
+    Ok(())
+}
+
+/**
+ * Removes an attendee from a team, the captain cannot be removed and the team
+ * cannot go below the minimum size of its sport
+ */
+pub async fn remove_team_member(db: &mut MySqlConnection, event: &EventConfig, uuid: &str, attendee: &IdentifiedAttendee, reference: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(delete_team_member(tx, event, uuid, attendee, reference, actor))).await?;
+    invalidate_team_pdf(uuid);
+    Ok(())
+}
+
+/**
+ * Renames a team, the name follows the same policy as the compose form
+ */
+pub async fn rename_team(db: &mut MySqlConnection, event: &EventConfig, uuid: &str, name: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(update_team_name(tx, event, uuid, name, actor))).await?;
+    invalidate_team_pdf(uuid);
+    Ok(())
+}
+
+/**
+ * Makes another member of the team its captain
+ */
+pub async fn transfer_captaincy(db: &mut MySqlConnection, event: &EventConfig, uuid: &str, new_captain: &IdentifiedAttendee, reference: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(update_team_captain(tx, event, uuid, new_captain, reference, actor))).await?;
+    invalidate_team_pdf(uuid);
+    Ok(())
+}
+
+/**
+ * Dissolves a team : its members are free to join another team of the sport
+ * and the school gets its slot back
+ *
+ * The team is only marked as deleted, with its members kept in
+ * deleted_team_members, so that the sport team can restore it
+ */
+pub async fn delete_team(db: &mut MySqlConnection, event: &EventConfig, uuid: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(soft_delete_team(tx, event, uuid, actor))).await?;
+    invalidate_team_pdf(uuid);
+    Ok(())
+}
+
+/**
+ * Brings a deleted team back, as long as the school still has a slot left in
+ * the sport and none of its members joined another team of the sport since
+ */
+pub async fn restore_team(db: &mut MySqlConnection, event: &EventConfig, uuid: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(undelete_team(tx, event, uuid, actor))).await?;
+    invalidate_team_pdf(uuid);
+    Ok(())
+}
+
+/**
+ * Asks the captain of the team with this join code to let the attendee in
+ *
+ * The attendee must be able to join the team right now, the same checks
+ * are run again when the captain accepts
+ */
+pub async fn request_to_join(db: &mut MySqlConnection, event: &EventConfig, join_code: &str, attendee: &IdentifiedAttendee, reference: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(insert_join_request(tx, event, join_code, attendee, reference, actor))).await
+}
+
+/**
+ * Adds the attendee of a pending join request to the team
+ */
+pub async fn accept_join_request(db: &mut MySqlConnection, event: &EventConfig, uuid: &str, request_id: u32, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(approve_join_request(tx, event, uuid, request_id, actor))).await?;
+    invalidate_team_pdf(uuid);
+    Ok(())
+}
+
+/**
+ * Refuses a pending join request, the attendee cannot ask to join this team again
+ */
+pub async fn decline_join_request(db: &mut MySqlConnection, event: &EventConfig, uuid: &str, request_id: u32, actor: &Actor) -> Result<(), EditTeamError> {
+    in_transaction(db, EditTeamError::Database, |tx| Box::pin(refuse_join_request(tx, event, uuid, request_id, actor))).await
+}
+
+/**
+ * Team shown to the attendees who open its join link
+ */
+pub async fn joinable_team(db: &mut MySqlConnection, event: &EventConfig, join_code: &str) -> Option<JoinableTeamView> {
+    let row = sqlx::query(
+        "SELECT t.name, qo.name, t.sport, t.gender, CONCAT(a.first_name, ' ', a.last_name),
+            (SELECT COUNT(*) FROM team_members tm WHERE tm.team_id = t.id)
+        FROM teams t
+        JOIN question_options qo ON t.school_id = qo.id
+        JOIN attendees a ON t.captain_id = a.id
+        WHERE t.join_code = ? AND t.event_id = ? AND t.deleted_at IS NULL"
+    )
+    .bind(join_code.to_uppercase())
+    .bind(event.event_id)
+    .fetch_optional(&mut *db)
+    .await
+    .ok()??;
+    let sport = team_sport(event, row.get(2), row.get(3)).ok()?;
+    Some(JoinableTeamView {
+        name: row.get(0),
+        school: row.get(1),
+        sport: sport.name,
+        gender: row.get(3),
+        captain: row.get(4),
+        nb_members: row.get(5),
+        min_players: sport.min_players,
+        max_players: sport.max_players
+    })
+}
+
+/**
+ * Join requests of a team still waiting for an answer of the captain, oldest first
+ */
+pub async fn pending_join_requests(db: &mut MySqlConnection, team_id: u32) -> Result<Vec<JoinRequestView>, sqlx::Error> {
+    let requests = sqlx::query(
+        "SELECT r.id, CONCAT(a.first_name, ' ', a.last_name), CONCAT(o.order_reference, '-', a.reference_index),
+            DATE_FORMAT(r.created_at, '%Y-%m-%d %H:%i')
+        FROM team_join_requests r
+        JOIN attendees a ON r.attendee_id = a.id
+        JOIN orders o ON a.order_id = o.id
+        WHERE r.team_id = ? AND r.status = 'pending'
+        ORDER BY r.id"
+    )
+    .bind(team_id)
+    .fetch_all(db)
+    .await?
+    .iter()
+    .map(|row| JoinRequestView {
+        id: row.get(0),
+        name: row.get(1),
+        attendee_ref: row.get(2),
+        created_at: row.get(3)
+    })
+    .collect();
+    Ok(requests)
+}
+
+/**
+ * Lifecycle status of a team with this many members
+ */
+pub fn team_status(event: &EventConfig, sport: &Sport, nb_members: usize, deleted: bool) -> TeamStatus {
+    let min_players = usize::from(sport.min_players);
+    if deleted {
+        TeamStatus::Withdrawn
+    }
+    else if nb_members <= 1 && min_players > 1 {
+        TeamStatus::Draft
+    }
+    else if nb_members < min_players {
+        TeamStatus::Incomplete
+    }
+    else if event.roster_frozen(&sport.name).is_some() {
+        TeamStatus::Locked
+    }
+    else {
+        TeamStatus::Valid
+    }
+}
+
+/**
+ * Status of a team in the team list, where teams of a sport removed from the configuration
+ * are still shown, as withdrawn or with an unknown sport
+ */
+fn listed_status(event: &EventConfig, sport: Option<&Sport>, nb_members: usize, deleted: bool) -> TeamStatus {
+    match sport {
+        Some(sport) => team_status(event, sport, nb_members, deleted),
+        None if deleted => TeamStatus::Withdrawn,
+        None => TeamStatus::UnknownSport
+    }
+}
+
+/**
+ * Filters of the team list, every filter is optional
+ */
+#[derive(Serialize, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct TeamListFilter {
+    pub school: Option<u32>,
+    pub sport: Option<String>,
+    /**
+     * Name of a TeamStatus, withdrawn teams are only listed with this filter
+     */
+    pub status: Option<String>,
+    /**
+     * Only draft and incomplete teams
+     */
+    pub below_min: bool,
+    /**
+     * Only teams whose sport deadline is at most this many days away
+     */
+    pub closing_in: Option<i64>
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TeamListFilter {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let field = |name: &str| request.query_value::<String>(name)
+            .and_then(|v| v.ok())
+            .filter(|v| !v.is_empty() && v != "none");
+        Outcome::Success(TeamListFilter {
+            school: field("school").and_then(|v| v.parse().ok()),
+            sport: field("sport"),
+            status: field("status"),
+            below_min: field("below_min").is_some_and(|v| v == "true"),
+            closing_in: field("closing_in").and_then(|v| v.parse().ok())
+        })
+    }
+}
+
+/**
+ * Teams of the event matching the filter, by school
+ */
+pub async fn list_teams(db: &mut MySqlConnection, event: &EventConfig, filter: &TeamListFilter) -> Result<Vec<TeamView>, sqlx::Error> {
+    let status = filter.status.as_deref().and_then(TeamStatus::from_name);
+    // Members of withdrawn teams are kept in deleted_team_members
+    let mut stmt = String::from(
+        "SELECT qo.name school, t.name, t.sport, t.gender, t.uuid, t.deleted_at IS NOT NULL,
+            CASE WHEN t.deleted_at IS NULL
+                THEN (SELECT COUNT(*) FROM team_members tm JOIN attendees a ON tm.attendee_id = a.id
+                    WHERE tm.team_id = t.id AND a.is_cancelled = 0)
+                ELSE (SELECT COUNT(*) FROM deleted_team_members dtm JOIN attendees a ON dtm.attendee_id = a.id
+                    WHERE dtm.team_id = t.id AND a.is_cancelled = 0)
+            END
+        FROM teams t JOIN question_options qo ON t.school_id = qo.id
+        WHERE t.event_id = ?"
+    );
+    match status {
+        Some(TeamStatus::Withdrawn) => stmt.push_str(" AND t.deleted_at IS NOT NULL"),
+        _ => stmt.push_str(" AND t.deleted_at IS NULL")
+    }
+    if filter.school.is_some() {
+        stmt.push_str(" AND t.school_id = ?");
+    }
+    if filter.sport.is_some() {
+        stmt.push_str(" AND t.sport = ?");
+    }
+    stmt.push_str(" ORDER BY school, t.sport, t.name");
+
+    let mut query = sqlx::query(&stmt).bind(event.event_id);
+    if let Some(school) = filter.school {
+        query = query.bind(school);
+    }
+    if let Some(sport) = &filter.sport {
+        query = query.bind(sport);
+    }
+
+    let mut teams = vec![];
+    for row in query.fetch_all(db).await? {
+        let sport_name: String = row.get(2);
+        let gender: String = row.get(3);
+        let deleted = row.get::<i64, usize>(5) != 0;
+        let nb_members: i64 = row.get(6);
+        // Teams of a sport removed from the configuration have no rules to be checked against
+        let sport = team_sport(event, sport_name.clone(), gender.clone()).ok();
+        let team_status = listed_status(event, sport.as_ref(), usize::try_from(nb_members).unwrap_or(0), deleted);
+        if status.is_some_and(|s| s != team_status)
+            || (filter.below_min && !team_status.is_below_min())
+            || filter.closing_in.is_some_and(|days| !event.deadline_within(&sport_name, days)) {
+            continue;
+        }
+        teams.push(TeamView {
+            name: row.get(1),
+            school: row.get(0),
+            sport: sport_name.clone(),
+            gender,
+            uuid: row.get(4),
+            status: team_status,
+            nb_members,
+            min_players: sport.map_or(0, |s| s.min_players),
+            deadline: event.team_deadline(&sport_name).map(|d| d.format(DATE_FORMAT).to_string())
+        });
+    }
+    Ok(teams)
+}
+
+/**
+ * Letters, digits, spaces and a few accents, from 1 to 64 characters,
+ * the same as the pattern of the compose form
+ */
+pub fn is_valid_team_name(name: &str) -> bool {
+    let length = name.chars().count();
+    (1..=64).contains(&length) && name.chars().all(|c| c.is_ascii_alphanumeric() || " éàêùü".contains(c))
+}
+
+/**
+ * Removes the cached PDF export of a team, it is generated again on next download
+ */
+pub fn invalidate_team_pdf(uuid: &str) {
+    let path = format!("ressources/teams/{uuid}.pdf");
+    if let Err(e) = std::fs::remove_file(&path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Unable to remove {path} : {e}");
+        }
+    }
+}
+
+/**
+ * Random code of 8 characters, without the ones easily mistaken for each other
+ */
+fn generate_join_code() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    let mut rng = rand::thread_rng();
+    (0..8).map(|_| char::from(ALPHABET[rng.gen_range(0..ALPHABET.len())])).collect()
+}
+
+/**
+ * Locks the team row so that concurrent edits of the same team are checked one after the other
+ *
+ * Once the roster freeze of the sport has passed, only staff members get the lock
+ */
+async fn lock_team(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, actor: &Actor) -> Result<LockedTeam, EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+
+    let row = sqlx::query("SELECT id, school_id, captain_id, sport, gender, name FROM teams WHERE uuid = ? AND event_id = ? AND deleted_at IS NULL FOR UPDATE")
+        .bind(uuid)
+        .bind(event.event_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or(EditTeamError::TeamNotFound)?;
+
+    let sport = team_sport(event, row.get(3), row.get(4))?;
+    check_roster_freeze(event, &sport.name, actor)?;
+
+    let team_id: u32 = row.get(0);
+    let nb_members: i64 = sqlx::query("SELECT COUNT(*) FROM team_members WHERE team_id = ?")
+        .bind(team_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .get(0);
+
+    Ok(LockedTeam {
+        id: team_id,
+        name: row.get(5),
+        school_id: row.get(1),
+        captain_id: row.get(2),
+        sport,
+        nb_members: usize::try_from(nb_members).unwrap_or(0)
+    })
+}
+
+/**
+ * Rules of the sport of a stored team, teams of a sport removed from the configuration cannot be edited
+ */
+pub fn team_sport(event: &EventConfig, sport_name: String, stored_gender: String) -> Result<Sport, EditTeamError> {
+    let gender = SportGender::from_stored_name(&stored_gender)
+        .ok_or_else(|| EditTeamError::Database(format!("Invalid team gender '{stored_gender}'")))?;
+    let attendee_gender = match gender {
+        SportGender::M => Some(AttendeeGender::M),
+        SportGender::F => Some(AttendeeGender::F),
+        SportGender::Mixed => None
+    };
+    match event.find_sport(&sport_name, attendee_gender) {
+        Ok(s) => Ok(s),
+        Err(e) => {
+            warn!("{}", e);
+            let mut report = TeamValidationReport::default();
+            report.add_team_problem(TeamProblemKind::UnknownSport, format!("{sport_name} is not available anymore, its teams cannot be edited"));
+            Err(EditTeamError::Invalid(report))
+        }
+    }
+}
+
+/**
+ * Refuses changes made after the roster freeze of the sport, except by sport admins
+ */
+fn check_roster_freeze(event: &EventConfig, sport_name: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    if matches!(actor, Actor::Staff(_)) {
+        return Ok(());
+    }
+    match event.roster_frozen(sport_name) {
+        Some(message) => {
+            let mut report = TeamValidationReport::default();
+            report.add_team_problem(TeamProblemKind::RosterFrozen, message);
+            Err(EditTeamError::Invalid(report))
+        }
+        None => Ok(())
+    }
+}
+
+/**
+ * Problems that keep the attendee from joining the locked team
+ */
+async fn check_new_member(tx: &mut MySqlConnection, event: &EventConfig, team: &LockedTeam, attendee: &IdentifiedAttendee, reference: &str) -> TeamValidationReport {
+    let sport = &team.sport;
+    let mut report = TeamValidationReport::default();
+
+    let status = validate_attendee(&mut *tx, event, attendee, sport).await;
+    if status != AttendeeStatus::Ok {
+        let name = attendee_name(&mut *tx, attendee.id).await;
+        let fullname = name.clone().unwrap_or_else(|| String::from(reference));
+        report.add_reference_problem(reference, name, Some(status), format!("{} ({reference})", status_message(status, &fullname, sport)));
+    }
+    if team.nb_members >= usize::from(sport.max_players) {
+        report.add_team_problem(
+            TeamProblemKind::InvalidSize,
+            format!("A {} team cannot have more than {} members", sport.name, sport.max_players)
+        );
+    }
+    if !sport.school_mix_allowed && attendee.school_id != team.school_id {
+        report.add_team_problem(
+            TeamProblemKind::SchoolMix,
+            format!("Members of a {} team should all come from the captain's school, which is not the case of {reference}", sport.name)
+        );
+    }
+    report
+}
+
+async fn insert_team_member(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, attendee: &IdentifiedAttendee, reference: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    let team = lock_team(&mut *tx, event, uuid, actor).await?;
+    let sport = &team.sport;
+    let mut report = check_new_member(&mut *tx, event, &team, attendee, reference).await;
+    if !report.is_valid() {
+        return Err(EditTeamError::Invalid(report));
+    }
+
+    let res = sqlx::query("INSERT INTO team_members(team_id, event_id, sport, attendee_id) VALUES (?, ?, ?, ?)")
+        .bind(team.id)
+        .bind(event.event_id)
+        .bind(&sport.name)
+        .bind(attendee.id)
+        .execute(&mut *tx)
+        .await;
+
+    match res {
+        Ok(_) => {
+            let change = Change {
+                team_id: Some(team.id),
+                attendee_id: Some(attendee.id),
+                after: Some(team.name.clone()),
+                details: String::from(reference),
+                ..Default::default()
+            };
+            audit::record(&mut *tx, event, actor, "add_member", change)
+                .await
+                .map_err(|e| EditTeamError::Database(e.to_string()))
+        }
+        // Joined another team of this sport since validate_attendee
+        Err(e) if is_duplicate_entry(&e) => {
+            let name = attendee_name(&mut *tx, attendee.id).await;
+            let fullname = name.clone().unwrap_or_else(|| String::from(reference));
+            let message = status_message(AttendeeStatus::AlreadyInATeam, &fullname, sport);
+            report.add_reference_problem(reference, name, Some(AttendeeStatus::AlreadyInATeam), message);
+            Err(EditTeamError::Invalid(report))
+        }
+        Err(e) => Err(EditTeamError::Database(e.to_string()))
+    }
+}
+
+async fn delete_team_member(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, attendee: &IdentifiedAttendee, reference: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+    let team = lock_team(&mut *tx, event, uuid, actor).await?;
+    let sport = &team.sport;
+    let mut report = TeamValidationReport::default();
+
+    let is_member = sqlx::query("SELECT attendee_id FROM team_members WHERE team_id = ? AND attendee_id = ?")
+        .bind(team.id)
+        .bind(attendee.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .is_some();
+
+    if !is_member {
+        let name = attendee_name(&mut *tx, attendee.id).await;
+        let fullname = name.clone().unwrap_or_else(|| String::from(reference));
+        report.add_reference_problem(reference, name, None, format!("{fullname} is not a member of this team"));
+        return Err(EditTeamError::Invalid(report));
+    }
+    if attendee.id == team.captain_id {
+        report.add_team_problem(TeamProblemKind::CaptainRemoval, String::from("The captain cannot be removed from the team"));
+    }
+    if !can_lose_member(team.nb_members, sport) {
+        report.add_team_problem(
+            TeamProblemKind::InvalidSize,
+            format!("A {} team needs at least {} members", sport.name, sport.min_players)
+        );
+    }
+    if !report.is_valid() {
+        return Err(EditTeamError::Invalid(report));
+    }
+
+    sqlx::query("DELETE FROM team_members WHERE team_id = ? AND attendee_id = ?")
+        .bind(team.id)
+        .bind(attendee.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let change = Change {
+        team_id: Some(team.id),
+        attendee_id: Some(attendee.id),
+        before: Some(team.name.clone()),
+        details: String::from(reference),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "remove_member", change).await.map_err(db_error)?;
+    Ok(())
+}
+
+/**
+ * A member can leave only a team above the minimum of its sport, a team
+ * already below it cannot lose anyone else
+ */
+fn can_lose_member(nb_members: usize, sport: &Sport) -> bool {
+    nb_members > usize::from(sport.min_players)
+}
+
+async fn update_team_name(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, name: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+    let team = lock_team(&mut *tx, event, uuid, actor).await?;
+
+    if !is_valid_team_name(name) {
+        let mut report = TeamValidationReport::default();
+        report.add_team_problem(
+            TeamProblemKind::InvalidName,
+            String::from("A team name is made of 1 to 64 letters, digits and spaces, remove weird accents")
+        );
+        return Err(EditTeamError::Invalid(report));
+    }
+
+    sqlx::query("UPDATE teams SET name = ? WHERE id = ?")
+        .bind(name)
+        .bind(team.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let change = Change {
+        team_id: Some(team.id),
+        before: Some(team.name),
+        after: Some(String::from(name)),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "rename", change).await.map_err(db_error)?;
+    Ok(())
+}
+
+async fn update_team_captain(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, new_captain: &IdentifiedAttendee, reference: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+    let team = lock_team(&mut *tx, event, uuid, actor).await?;
+    let mut report = TeamValidationReport::default();
+
+    let is_member = sqlx::query("SELECT attendee_id FROM team_members WHERE team_id = ? AND attendee_id = ?")
+        .bind(team.id)
+        .bind(new_captain.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .is_some();
+
+    let name = attendee_name(&mut *tx, new_captain.id).await;
+    let fullname = name.clone().unwrap_or_else(|| String::from(reference));
+    if !is_member {
+        report.add_reference_problem(reference, name, None, format!("{fullname} is not a member of this team"));
+    }
+    else if new_captain.id == team.captain_id {
+        report.add_reference_problem(reference, name, None, format!("{fullname} is already the captain of this team"));
+    }
+    if !report.is_valid() {
+        return Err(EditTeamError::Invalid(report));
+    }
+
+    sqlx::query("UPDATE teams SET captain_id = ? WHERE id = ?")
+        .bind(new_captain.id)
+        .bind(team.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let change = Change {
+        team_id: Some(team.id),
+        attendee_id: Some(new_captain.id),
+        before: Some(format!("attendee {}", team.captain_id)),
+        after: Some(format!("attendee {} ({fullname})", new_captain.id)),
+        details: String::from(reference)
+    };
+    audit::record(&mut *tx, event, actor, "captain", change).await.map_err(db_error)?;
+    Ok(())
+}
+
+async fn soft_delete_team(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+
+    // Teams of a sport removed from the configuration can still be deleted, no lock_team here
+    let row = sqlx::query("SELECT id, name, sport FROM teams WHERE uuid = ? AND event_id = ? AND deleted_at IS NULL FOR UPDATE")
+        .bind(uuid)
+        .bind(event.event_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or(EditTeamError::TeamNotFound)?;
+    let team_id: u32 = row.get(0);
+    let name: String = row.get(1);
+    let sport_name: String = row.get(2);
+    check_roster_freeze(event, &sport_name, actor)?;
+
+    let members: Vec<u32> = sqlx::query("SELECT attendee_id FROM team_members WHERE team_id = ? ORDER BY attendee_id")
+        .bind(team_id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .iter()
+        .map(|r| r.get(0))
+        .collect();
+
+    sqlx::query("INSERT INTO deleted_team_members(team_id, attendee_id) SELECT team_id, attendee_id FROM team_members WHERE team_id = ?")
+        .bind(team_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    sqlx::query("DELETE FROM team_members WHERE team_id = ?")
+        .bind(team_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    sqlx::query("UPDATE teams SET deleted_at = NOW(), deleted_by = ? WHERE id = ?")
+        .bind(actor.to_string())
+        .bind(team_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    let members: Vec<String> = members.iter().map(|id| id.to_string()).collect();
+    let change = Change {
+        team_id: Some(team_id),
+        before: Some(format!("'{name}', members : {}", members.join(", "))),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "delete", change).await.map_err(db_error)?;
+    Ok(())
+}
+
+async fn undelete_team(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+
+    let row = sqlx::query("SELECT id, school_id, sport, gender, name FROM teams WHERE uuid = ? AND event_id = ? AND deleted_at IS NOT NULL FOR UPDATE")
+        .bind(uuid)
+        .bind(event.event_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or(EditTeamError::TeamNotFound)?;
+    let team_id: u32 = row.get(0);
+    let school_id: u32 = row.get(1);
+    let sport = team_sport(event, row.get(2), row.get(3))?;
+    let name: String = row.get(4);
+    let mut report = TeamValidationReport::default();
+
+    // Same lock as insert_team, a restore takes a slot like a new registration
+    sqlx::query("SELECT id FROM question_options WHERE id = ? FOR UPDATE")
+        .bind(school_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let school_nb_teams: i64 = sqlx::query(
+        "SELECT COUNT(*) FROM teams WHERE event_id = ? AND school_id = ? AND sport = ? AND deleted_at IS NULL"
+    )
+    .bind(event.event_id)
+    .bind(school_id)
+    .bind(&sport.name)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .get(0);
+    if school_nb_teams >= i64::from(sport.max_teams_per_school) {
+        report.add_team_problem(TeamProblemKind::SchoolQuota, school_quota_message(&sport));
+    }
+
+    let taken = sqlx::query(
+        "SELECT dtm.attendee_id, CONCAT(o.order_reference, '-', a.reference_index)
+        FROM deleted_team_members dtm
+        JOIN attendees a ON dtm.attendee_id = a.id
+        JOIN orders o ON a.order_id = o.id
+        JOIN team_members tm ON tm.attendee_id = dtm.attendee_id AND tm.event_id = ? AND tm.sport = ?
+        WHERE dtm.team_id = ?"
+    )
+    .bind(event.event_id)
+    .bind(&sport.name)
+    .bind(team_id)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    for member in taken {
+        let reference: String = member.get(1);
+        let name = attendee_name(&mut *tx, member.get(0)).await;
+        let fullname = name.clone().unwrap_or_else(|| reference.clone());
+        let message = status_message(AttendeeStatus::AlreadyInATeam, &fullname, &sport);
+        report.add_reference_problem(&reference, name, Some(AttendeeStatus::AlreadyInATeam), message);
+    }
+    if !report.is_valid() {
+        return Err(EditTeamError::Invalid(report));
+    }
+
+    sqlx::query(
+        "INSERT INTO team_members(team_id, event_id, sport, attendee_id)
+        SELECT team_id, ?, ?, attendee_id FROM deleted_team_members WHERE team_id = ?"
+    )
+    .bind(event.event_id)
+    .bind(&sport.name)
+    .bind(team_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+    sqlx::query("DELETE FROM deleted_team_members WHERE team_id = ?")
+        .bind(team_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    sqlx::query("UPDATE teams SET deleted_at = NULL, deleted_by = NULL WHERE id = ?")
+        .bind(team_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let change = Change {
+        team_id: Some(team_id),
+        after: Some(format!("'{name}'")),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "restore", change).await.map_err(db_error)?;
+    Ok(())
+}
+
+async fn insert_join_request(tx: &mut MySqlConnection, event: &EventConfig, join_code: &str, attendee: &IdentifiedAttendee, reference: &str, actor: &Actor) -> Result<(), EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+
+    let uuid: String = sqlx::query("SELECT uuid FROM teams WHERE join_code = ? AND event_id = ? AND deleted_at IS NULL")
+        .bind(join_code.to_uppercase())
+        .bind(event.event_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .ok_or(EditTeamError::TeamNotFound)?
+        .get(0);
+    let team = lock_team(&mut *tx, event, &uuid, actor).await?;
+    let mut report = check_new_member(&mut *tx, event, &team, attendee, reference).await;
+    if !report.is_valid() {
+        return Err(EditTeamError::Invalid(report));
+    }
+
+    let previous: Option<String> = sqlx::query("SELECT status FROM team_join_requests WHERE team_id = ? AND attendee_id = ?")
+        .bind(team.id)
+        .bind(attendee.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .map(|row| row.get(0));
+    let name = attendee_name(&mut *tx, attendee.id).await;
+    match previous.as_deref() {
+        None => {
+            sqlx::query("INSERT INTO team_join_requests(team_id, attendee_id) VALUES (?, ?)")
+                .bind(team.id)
+                .bind(attendee.id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+        Some("pending") => {
+            report.add_reference_problem(reference, name, None, format!("You already asked to join {}, wait for the answer of its captain", team.name));
+            return Err(EditTeamError::Invalid(report));
+        }
+        Some("declined") => {
+            report.add_reference_problem(reference, name, None, format!("The captain of {} declined your request", team.name));
+            return Err(EditTeamError::Invalid(report));
+        }
+        // Accepted then removed from the team, they may ask again
+        Some(_) => {
+            sqlx::query("UPDATE team_join_requests SET status = 'pending', created_at = NOW(), decided_at = NULL WHERE team_id = ? AND attendee_id = ?")
+                .bind(team.id)
+                .bind(attendee.id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+    }
+
+    let change = Change {
+        team_id: Some(team.id),
+        attendee_id: Some(attendee.id),
+        details: String::from(reference),
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "join_request", change).await.map_err(db_error)?;
+    Ok(())
+}
+
+/**
+ * Pending request of the team, locked until commit, with the attendee id and reference
+ */
+async fn lock_join_request(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, request_id: u32) -> Result<(u32, String), EditTeamError> {
+    let row = sqlx::query(
+        "SELECT r.attendee_id, CONCAT(o.order_reference, '-', a.reference_index)
+        FROM team_join_requests r
+        JOIN teams t ON r.team_id = t.id
+        JOIN attendees a ON r.attendee_id = a.id
+        JOIN orders o ON a.order_id = o.id
+        WHERE r.id = ? AND t.uuid = ? AND t.event_id = ? AND r.status = 'pending'
+        FOR UPDATE"
+    )
+    .bind(request_id)
+    .bind(uuid)
+    .bind(event.event_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| EditTeamError::Database(e.to_string()))?
+    .ok_or(EditTeamError::RequestNotFound)?;
+    Ok((row.get(0), row.get(1)))
+}
+
+async fn approve_join_request(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, request_id: u32, actor: &Actor) -> Result<(), EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+    // Team first then request, in the same order as refuse_join_request
+    lock_team(&mut *tx, event, uuid, actor).await?;
+    let (attendee_id, reference) = lock_join_request(&mut *tx, event, uuid, request_id).await?;
+
+    let attendee = match get_attendee(&mut *tx, event, attendee_id).await {
+        Ok(a) => a,
+        Err(e) => {
+            let mut report = TeamValidationReport::default();
+            let name = attendee_name(&mut *tx, attendee_id).await;
+            report.add_reference_problem(&reference, name, None, format!("{reference} cannot join the team : {e}"));
+            return Err(EditTeamError::Invalid(report));
+        }
+    };
+    // Same checks and audit entry as a member added by the captain
+    insert_team_member(&mut *tx, event, uuid, &attendee, &reference, actor).await?;
+
+    sqlx::query("UPDATE team_join_requests SET status = 'accepted', decided_at = NOW() WHERE id = ?")
+        .bind(request_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    Ok(())
+}
+
+async fn refuse_join_request(tx: &mut MySqlConnection, event: &EventConfig, uuid: &str, request_id: u32, actor: &Actor) -> Result<(), EditTeamError> {
+    let db_error = |e: sqlx::Error| EditTeamError::Database(e.to_string());
+    let team = lock_team(&mut *tx, event, uuid, actor).await?;
+    let (attendee_id, reference) = lock_join_request(&mut *tx, event, uuid, request_id).await?;
+
+    sqlx::query("UPDATE team_join_requests SET status = 'declined', decided_at = NOW() WHERE id = ?")
+        .bind(request_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+    let change = Change {
+        team_id: Some(team.id),
+        attendee_id: Some(attendee_id),
+        details: reference,
+        ..Default::default()
+    };
+    audit::record(&mut *tx, event, actor, "decline_request", change).await.map_err(db_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ini::Ini;
+
+    use super::*;
+    use crate::config::AppConfig;
+
+    fn frozen_event() -> EventConfig {
+        let ini = Ini::load_from_str("
+[event:games]
+name = Games
+event_id = 2
+account_id = 1
+athlete_ticket_ids = 1,2
+male_sport_question_ids = (5, 6)
+female_sport_question_ids = (5, 7)
+gender_question_id = 17
+school_question_id = 15
+phone_question_id = 4
+check_in_read_only = false
+team_registration_open = true
+
+[games:Volleyball]
+gender = mixed
+max_teams_per_school = 2
+min = 6
+max = 10
+roster_freeze = 2023-05-01 23:59
+").unwrap();
+        let config = AppConfig::from_ini(&ini).unwrap_or_else(|e| panic!("{e:?}"));
+        config.event("games").unwrap().clone()
+    }
+
+    #[test]
+    fn delegation_leader_refused_after_roster_freeze() {
+        let event = frozen_event();
+        let actor = Actor::DelegationLeader(String::from("leader"));
+        assert!(matches!(check_roster_freeze(&event, "Volleyball", &actor), Err(EditTeamError::Invalid(_))));
+    }
+
+    #[test]
+    fn captain_refused_after_roster_freeze() {
+        let event = frozen_event();
+        assert!(matches!(check_roster_freeze(&event, "Volleyball", &Actor::Captain(12)), Err(EditTeamError::Invalid(_))));
+    }
+
+    #[test]
+    fn sport_admin_allowed_after_roster_freeze() {
+        let event = frozen_event();
+        let actor = Actor::Staff(String::from("admin"));
+        assert!(check_roster_freeze(&event, "Volleyball", &actor).is_ok());
+    }
+
+    fn volleyball() -> Sport {
+        frozen_event().find_sport("Volleyball", None).unwrap()
+    }
+
+    #[test]
+    fn member_can_leave_team_above_minimum() {
+        assert!(can_lose_member(7, &volleyball()));
+    }
+
+    #[test]
+    fn member_cannot_leave_team_at_minimum() {
+        assert!(!can_lose_member(6, &volleyball()));
+    }
+
+    #[test]
+    fn member_cannot_leave_team_below_minimum() {
+        assert!(!can_lose_member(4, &volleyball()));
+    }
+
+    #[test]
+    fn team_of_removed_sport_is_listed() {
+        let event = frozen_event();
+        assert_eq!(listed_status(&event, None, 3, false), TeamStatus::UnknownSport);
+        assert_eq!(listed_status(&event, None, 3, true), TeamStatus::Withdrawn);
+        assert_eq!(listed_status(&event, Some(&volleyball()), 3, false), TeamStatus::Incomplete);
+    }
+}